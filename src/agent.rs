@@ -2,10 +2,12 @@ use std::collections::HashMap;
 
 use anyhow::Result;
 use schemars::{JsonSchema, Schema};
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::edit::{self, Edit};
+use crate::edit::{self, Edit, EditError, LineEdit, RegexEdit};
+use crate::eol;
+use crate::syntax::{self, Lang};
 
 pub struct Seed<'a> {
     pub system: &'a str,
@@ -32,6 +34,7 @@ pub fn placeholder_if_empty(field: &str) -> &str {
 
 pub struct Ctx<'a> {
     original: &'a str,
+    fuzzy_threshold: f64,
 }
 
 pub type Reply = std::result::Result<String, String>;
@@ -53,7 +56,36 @@ impl Step {
     }
 }
 
-type Handler = Box<dyn Fn(&mut String, &Ctx, Value) -> Result<Step>>;
+// The text being edited, as it was when the current turn started, and where the turn's
+// edits have written in it.
+pub struct Buffer {
+    text: String,
+    started: String,
+    written: edit::Written,
+}
+
+impl Buffer {
+    fn new_turn(&mut self) {
+        self.started = self.text.clone();
+        self.written = edit::Written::default();
+    }
+
+    // Why `edit` no longer resolves, when it did against the text the model last saw:
+    // an earlier edit this turn has rewritten part of it.
+    fn conflict(&self, edit: &Edit, fuzzy_threshold: f64) -> Option<EditError> {
+        let splices = edit::resolve(&self.started, edit, fuzzy_threshold).ok()?;
+        self.written.check_started(&self.text, &splices).err()
+    }
+
+    fn splice(&mut self, splices: &[edit::Splice]) -> std::result::Result<(), EditError> {
+        self.written.check(&self.text, splices)?;
+        self.text = edit::splice(&self.text, splices);
+        self.written.record(splices);
+        Ok(())
+    }
+}
+
+type Handler = Box<dyn Fn(&mut Buffer, &Ctx, Value) -> Result<Step>>;
 
 pub struct Tool {
     pub name: &'static str,
@@ -66,14 +98,20 @@ impl Tool {
     fn new<A: JsonSchema + DeserializeOwned + 'static>(
         name: &'static str,
         description: &'static str,
-        handler: impl Fn(&mut String, &Ctx, A) -> Step + 'static,
+        handler: impl Fn(&mut Buffer, &Ctx, A) -> Step + 'static,
     ) -> Tool {
         Tool {
             name,
             description,
             input_schema: schemars::schema_for!(A),
             run: Box::new(move |buf, ctx, args| {
-                Ok(handler(buf, ctx, serde_json::from_value(args)?))
+                let args = match args {
+                    Value::String(raw) => serde_json::from_str(&raw).map_err(|e| {
+                        anyhow::anyhow!("arguments were not valid JSON ({e}): {raw}")
+                    })?,
+                    args => serde_json::from_value(args)?,
+                };
+                Ok(handler(buf, ctx, args))
             }),
         }
     }
@@ -82,6 +120,49 @@ impl Tool {
 #[derive(JsonSchema, serde::Deserialize)]
 struct NoArgs {}
 
+#[derive(JsonSchema, serde::Deserialize)]
+struct View {
+    #[schemars(description = "prefix each line with its number, for edit_lines")]
+    #[serde(default)]
+    line_numbers: bool,
+}
+
+// "ok", unless `old` only matched loosely: then the model is shown what was replaced, so it
+// can tell whether that was the text it meant.
+fn matched_reply(src: &str, edit: &Edit, splices: &[edit::Splice]) -> String {
+    match splices.first().map(|s| &src[s.range.clone()]) {
+        Some(replaced) if replaced != edit.old => format!(
+            "ok, but `old` did not match exactly; this was replaced instead:\n{replaced}\nview the text and fix it if that was not the intended place"
+        ),
+        _ => "ok".into(),
+    }
+}
+
+// `result` is what to tell the model about the edit `tool` made.
+fn attempted(tool: &'static str, edit: Edit, result: Result<String, EditError>) -> Step {
+    match result {
+        Ok(reply) => Step::Continue {
+            reply: Ok(reply),
+            attempt: Some(Attempt {
+                tool,
+                edit,
+                error: None,
+            }),
+        },
+        Err(err) => {
+            let msg = err.to_string();
+            Step::Continue {
+                reply: Err(msg.clone()),
+                attempt: Some(Attempt {
+                    tool,
+                    edit,
+                    error: Some(msg),
+                }),
+            }
+        }
+    }
+}
+
 pub fn tools() -> Vec<Tool> {
     vec![
         Tool::new::<Edit>(
@@ -90,42 +171,76 @@ pub fn tools() -> Vec<Tool> {
                 (whitespace and indentation included); make it long enough to be unique, or set \
                 `replace_all`. `new` is the replacement — empty to delete; to insert, include \
                 surrounding text in both `old` and `new`. Call this several times in one turn to \
-                make several edits.",
-            |buf, _ctx, e: Edit| match edit::apply(buf, &e) {
-                Ok(next) => {
-                    *buf = next;
-                    Step::Continue {
-                        reply: Ok("ok".into()),
-                        attempt: Some(Attempt {
-                            edit: e,
-                            error: None,
-                        }),
+                make several edits, each to a different region.",
+            |buf, ctx, e: Edit| {
+                let result = match edit::resolve(&buf.text, &e, ctx.fuzzy_threshold) {
+                    Ok(splices) => {
+                        let reply = matched_reply(&buf.text, &e, &splices);
+                        buf.splice(&splices).map(|()| reply)
                     }
-                }
-                Err(err) => {
-                    let msg = err.to_string();
-                    Step::Continue {
-                        reply: Err(msg.clone()),
-                        attempt: Some(Attempt {
-                            edit: e,
-                            error: Some(msg),
-                        }),
+                    Err(err) => Err(buf.conflict(&e, ctx.fuzzy_threshold).unwrap_or(err)),
+                };
+                attempted("edit", e, result)
+            },
+        ),
+        Tool::new::<LineEdit>(
+            "edit_lines",
+            "Replace lines `start` through `end` (inclusive, counting from 1) of the current \
+                text with `new`. Use it when `edit` keeps matching repeated boilerplate. View with \
+                `line_numbers` first, and remember that earlier edits shift later line numbers.",
+            |buf, _ctx, e: LineEdit| {
+                let (result, old) = match edit::resolve_lines(&buf.text, &e) {
+                    Ok(s) => {
+                        let old = buf.text[s.range.clone()].to_string();
+                        (buf.splice(&[s]).map(|()| "ok".into()), old)
                     }
-                }
+                    Err(err) => (Err(err), String::new()),
+                };
+                let edit = Edit {
+                    old,
+                    new: e.new,
+                    replace_all: false,
+                };
+                attempted("edit_lines", edit, result)
             },
         ),
-        Tool::new::<NoArgs>(
+        Tool::new::<RegexEdit>(
+            "regex_replace",
+            "Replace every match of a regular expression (or the first `max_count`) in one \
+                call, with $1-style capture group references in `replacement`. Prefer it over \
+                many `edit` calls for mechanical renames and rewrites. Replies with the number \
+                of replacements made.",
+            |buf, _ctx, e: RegexEdit| {
+                let result = edit::resolve_regex(&buf.text, &e).and_then(|splices| {
+                    buf.splice(&splices)?;
+                    let n = splices.len();
+                    let plural = if n == 1 { "" } else { "s" };
+                    Ok(format!("{n} replacement{plural}"))
+                });
+                let edit = Edit {
+                    old: e.pattern,
+                    new: e.replacement,
+                    replace_all: e.max_count.is_none(),
+                };
+                attempted("regex_replace", edit, result)
+            },
+        ),
+        Tool::new::<View>(
             "view",
             "Return the current text, with all edits so far applied. Use it to re-anchor if \
                 you've lost track of the exact contents.",
-            |buf, _ctx, _: NoArgs| Step::reply(Ok(buf.clone())),
+            |buf, _ctx, v: View| match v.line_numbers {
+                true => Step::reply(Ok(edit::numbered(&buf.text))),
+                false => Step::reply(Ok(buf.text.clone())),
+            },
         ),
         Tool::new::<NoArgs>(
             "reset",
             "Discard all edits and restore the original selected text. Returns it.",
             |buf, ctx, _: NoArgs| {
-                *buf = ctx.original.to_owned();
-                Step::reply(Ok(buf.clone()))
+                buf.text = ctx.original.to_owned();
+                buf.new_turn();
+                Step::reply(Ok(buf.text.clone()))
             },
         ),
         Tool::new::<NoArgs>(
@@ -143,21 +258,87 @@ pub struct RawCall {
     pub args: Value,
 }
 
+// For backends that receive arguments as a JSON string; an empty one means no arguments.
+// One that doesn't parse is kept as a string, and running the call replies with the error.
+pub fn arguments(raw: &str) -> Value {
+    if raw.trim().is_empty() {
+        return Value::Object(Default::default());
+    }
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ToolResult {
     pub id: String,
     pub result: Reply,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub cache_read_tokens: u64,
+    #[serde(default)]
+    pub cache_write_tokens: u64,
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+        self.cache_write_tokens += other.cache_write_tokens;
+    }
+}
+
 pub trait Model {
     fn turn(&mut self, results: Vec<ToolResult>) -> Result<Vec<RawCall>>;
+
+    fn usage(&self) -> Usage {
+        Usage::default()
+    }
+
+    fn transcript(&self) -> Result<Value> {
+        anyhow::bail!("this backend can't save its conversation")
+    }
+
+    fn follow_up(&mut self, _results: Vec<ToolResult>, _transform: &str) -> Result<()> {
+        anyhow::bail!("this backend can't continue a conversation")
+    }
 }
 
 pub const DEFAULT_MAX_TURNS: usize = 25;
 
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub max_turns: usize,
+    pub fuzzy_threshold: f64,
+    pub lang: Option<Lang>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            max_turns: DEFAULT_MAX_TURNS,
+            fuzzy_threshold: edit::DEFAULT_FUZZY_THRESHOLD,
+            lang: None,
+        }
+    }
+}
+
 const MAX_CONSECUTIVE_FAILURES: usize = 3;
 
-#[derive(Debug)]
+// How often a finish that broke the syntax is sent back, and the extra turns each allows.
+const SYNTAX_FIX_ROUNDS: usize = 2;
+const SYNTAX_FIX_TURNS: usize = 3;
+
+#[derive(Debug, Serialize)]
 pub struct Attempt {
+    /// Which tool made the edit; for `regex_replace`, `old` and `new` are the pattern and
+    /// replacement.
+    pub tool: &'static str,
+    #[serde(flatten)]
     pub edit: Edit,
     pub error: Option<String>,
 }
@@ -166,46 +347,136 @@ pub struct Attempt {
 pub struct Outcome {
     pub text: String,
     pub attempts: Vec<Attempt>,
+    pub turns: usize,
+    pub pending: Vec<ToolResult>,
+    // Where the text stopped parsing, if the original parsed and the model ran out of
+    // chances to fix it.
+    pub syntax_errors: Vec<String>,
+}
+
+pub fn run(model: &mut dyn Model, original: String, opts: &Options) -> Result<Outcome> {
+    resume(model, &original, original.clone(), opts)
+}
+
+// Edits happen on `\n`-normalized text; the result and the logged edits get `current`'s line
+// endings back.
+pub fn resume(
+    model: &mut dyn Model,
+    original: &str,
+    current: String,
+    opts: &Options,
+) -> Result<Outcome> {
+    let style = |s: String| eol::styled(&current, &s);
+    let out = edit_loop(
+        model,
+        &eol::normalize(original),
+        eol::normalize(&current),
+        opts,
+    )?;
+    let attempts = out
+        .attempts
+        .into_iter()
+        .map(|Attempt { tool, edit, error }| Attempt {
+            tool,
+            edit: Edit {
+                old: style(edit.old),
+                new: style(edit.new),
+                ..edit
+            },
+            error,
+        })
+        .collect();
+    Ok(Outcome {
+        text: eol::restore(&current, &out.text),
+        attempts,
+        ..out
+    })
 }
 
-pub fn run(model: &mut dyn Model, original: String, max_turns: usize) -> Result<Outcome> {
+fn edit_loop(
+    model: &mut dyn Model,
+    original: &str,
+    current: String,
+    opts: &Options,
+) -> Result<Outcome> {
     let tools = tools();
     let by_name: HashMap<&str, &Tool> = tools.iter().map(|t| (t.name, t)).collect();
     let ctx = Ctx {
-        original: &original,
+        original,
+        fuzzy_threshold: opts.fuzzy_threshold,
     };
 
-    let mut current = original.clone();
+    let mut buf = Buffer {
+        started: current.clone(),
+        text: current,
+        written: edit::Written::default(),
+    };
     let mut attempts = Vec::new();
     let mut consecutive_failures = 0;
     let mut pending: Vec<ToolResult> = Vec::new();
 
-    for _ in 0..max_turns {
+    // Only hold the result to the syntax if the original met it.
+    let syntax = opts
+        .lang
+        .filter(|&l| syntax::errors(l, original).is_empty());
+    let mut syntax_fixes = 0;
+    let mut limit = opts.max_turns;
+    let mut turns = 0;
+
+    while turns < limit {
+        turns += 1;
+        crate::progress::turn(turns);
         let calls = model.turn(std::mem::take(&mut pending))?;
         if calls.is_empty() {
+            // Stopping without finish is held to the same check, but there is no call to
+            // answer, so the errors go back as a follow-up message.
+            if let Some(msg) = syntax_rejection(syntax, &buf.text, &mut syntax_fixes) {
+                model.follow_up(Vec::new(), &msg)?;
+                limit = limit.max(turns + SYNTAX_FIX_TURNS);
+                continue;
+            }
             return Ok(Outcome {
-                text: current,
+                syntax_errors: syntax_errors(syntax, &buf.text),
+                text: buf.text,
                 attempts,
+                turns,
+                pending: Vec::new(),
             });
         }
 
         let mut results = Vec::with_capacity(calls.len());
         let mut edits_attempted = 0;
         let mut edits_failed = 0;
+        let mut finished = false;
+        buf.new_turn();
 
         for RawCall { id, name, args } in calls {
+            if finished {
+                let skipped = "not run: finish was called earlier in the same turn";
+                results.push(ToolResult {
+                    id,
+                    result: Err(skipped.into()),
+                });
+                continue;
+            }
+
+            crate::progress::tool(&name);
             let step = match by_name.get(name.as_str()) {
-                Some(tool) => (tool.run)(&mut current, &ctx, args),
+                Some(tool) => (tool.run)(&mut buf, &ctx, args),
                 None => Err(anyhow::anyhow!("unknown tool {name:?}")),
             };
 
             let (reply, attempt) = match step {
-                Ok(Step::Finish) => {
-                    return Ok(Outcome {
-                        text: current,
-                        attempts,
-                    })
-                }
+                Ok(Step::Finish) => match syntax_rejection(syntax, &buf.text, &mut syntax_fixes) {
+                    Some(msg) => {
+                        limit = limit.max(turns + SYNTAX_FIX_TURNS);
+                        (Err(format!("not finished: {msg}")), None)
+                    }
+                    None => {
+                        finished = true;
+                        (Ok("finished".into()), None)
+                    }
+                },
                 Ok(Step::Continue { reply, attempt }) => (reply, attempt),
                 Err(err) => (Err(err.to_string()), None),
             };
@@ -216,11 +487,22 @@ pub fn run(model: &mut dyn Model, original: String, max_turns: usize) -> Result<
                     edits_failed += 1;
                 }
                 attempts.push(attempt);
+                crate::progress::edits(attempts.iter().filter(|a| a.error.is_none()).count());
             }
 
             results.push(ToolResult { id, result: reply });
         }
 
+        if finished {
+            return Ok(Outcome {
+                syntax_errors: syntax_errors(syntax, &buf.text),
+                text: buf.text,
+                attempts,
+                turns,
+                pending: results,
+            });
+        }
+
         if edits_attempted > 0 && edits_failed == edits_attempted {
             consecutive_failures += 1;
             if consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
@@ -235,7 +517,35 @@ pub fn run(model: &mut dyn Model, original: String, max_turns: usize) -> Result<
         pending = results;
     }
 
-    anyhow::bail!("edit loop hit its {max_turns}-turn limit")
+    anyhow::bail!("edit loop hit its {limit}-turn limit")
+}
+
+// What to tell the model when `text` no longer parses, while it has fix rounds left.
+fn syntax_rejection(syntax: Option<Lang>, text: &str, fixes: &mut usize) -> Option<String> {
+    let lang = syntax?;
+    let errors = syntax::errors(lang, text);
+    if errors.is_empty() || *fixes >= SYNTAX_FIX_ROUNDS {
+        return None;
+    }
+    *fixes += 1;
+    Some(format!(
+        "the text no longer parses as {lang:?}, though the original did:\n{}\nfix it, then call finish",
+        errors.join("\n")
+    ))
+}
+
+fn syntax_errors(syntax: Option<Lang>, text: &str) -> Vec<String> {
+    let Some(lang) = syntax else {
+        return Vec::new();
+    };
+    let errors = syntax::errors(lang, text);
+    if !errors.is_empty() {
+        tracing::warn!(
+            "the result no longer parses as {lang:?}: {}",
+            errors.join("; ")
+        );
+    }
+    errors
 }
 
 #[cfg(test)]
@@ -246,6 +556,7 @@ mod tests {
     struct ScriptedModel {
         turns: std::vec::IntoIter<Vec<RawCall>>,
         seen: Vec<Vec<ToolResult>>,
+        follow_ups: Vec<String>,
     }
 
     impl ScriptedModel {
@@ -253,6 +564,7 @@ mod tests {
             ScriptedModel {
                 turns: turns.into_iter(),
                 seen: Vec::new(),
+                follow_ups: Vec::new(),
             }
         }
     }
@@ -262,6 +574,12 @@ mod tests {
             self.seen.push(results);
             Ok(self.turns.next().unwrap_or_default())
         }
+
+        fn follow_up(&mut self, results: Vec<ToolResult>, transform: &str) -> Result<()> {
+            self.seen.push(results);
+            self.follow_ups.push(transform.to_string());
+            Ok(())
+        }
     }
 
     fn edit_call(id: &str, old: &str, new: &str) -> RawCall {
@@ -280,16 +598,93 @@ mod tests {
         }
     }
 
-    const TURNS: usize = 25;
-
     #[test]
     fn edit_then_finish() {
         let mut m = ScriptedModel::new(vec![
             vec![edit_call("1", "Me like", "I like")],
             vec![call("2", "finish")],
         ]);
-        let out = run(&mut m, "Me like toast.".into(), TURNS).unwrap().text;
-        assert_eq!(out, "I like toast.");
+        let out = run(&mut m, "Me like toast.".into(), &Options::default()).unwrap();
+        assert_eq!(out.text, "I like toast.");
+        assert_eq!(out.turns, 2);
+    }
+
+    #[test]
+    fn crlf_is_hidden_from_the_model_and_restored() {
+        let mut m = ScriptedModel::new(vec![
+            vec![call("1", "view"), edit_call("2", "a\nb", "a\nB\nc")],
+            vec![call("3", "finish")],
+        ]);
+        let out = run(&mut m, "a\r\nb\r\n".into(), &Options::default()).unwrap();
+        assert_eq!(m.seen[1][0].result, Ok("a\nb\n".into()));
+        assert_eq!(out.text, "a\r\nB\r\nc\r\n");
+        assert_eq!(out.attempts[0].edit.new, "a\r\nB\r\nc");
+    }
+
+    #[test]
+    fn line_numbered_view_then_edit_lines() {
+        let src = "x = 1\nx = 1\nx = 1\n";
+        let mut m = ScriptedModel::new(vec![
+            vec![RawCall {
+                id: "1".into(),
+                name: "view".into(),
+                args: json!({ "line_numbers": true }),
+            }],
+            vec![RawCall {
+                id: "2".into(),
+                name: "edit_lines".into(),
+                args: json!({ "start": 2, "end": 2, "new": "x = 2" }),
+            }],
+            vec![call("3", "finish")],
+        ]);
+        let out = run(&mut m, src.into(), &Options::default()).unwrap();
+        assert_eq!(out.text, "x = 1\nx = 2\nx = 1\n");
+        assert_eq!(
+            m.seen[1][0].result,
+            Ok("1\tx = 1\n2\tx = 1\n3\tx = 1\n".into())
+        );
+        assert_eq!(out.attempts[0].edit.old, "x = 1\n");
+        assert_eq!(out.attempts[0].error, None);
+    }
+
+    #[test]
+    fn regex_replace_reports_its_count() {
+        let mut m = ScriptedModel::new(vec![
+            vec![RawCall {
+                id: "1".into(),
+                name: "regex_replace".into(),
+                args: json!({ "pattern": "%s", "replacement": "{}" }),
+            }],
+            vec![call("2", "finish")],
+        ]);
+        let out = run(
+            &mut m,
+            r#"printf("%s and %s", a, b)"#.into(),
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(out.text, r#"printf("{} and {}", a, b)"#);
+        assert_eq!(m.seen[1][0].result, Ok("2 replacements".into()));
+        assert_eq!(out.attempts[0].tool, "regex_replace");
+        assert_eq!(out.attempts[0].edit.old, "%s");
+        assert!(out.attempts[0].edit.replace_all);
+    }
+
+    #[test]
+    fn attempts_serialize_flat() {
+        let attempt = Attempt {
+            tool: "edit",
+            edit: Edit {
+                old: "a".into(),
+                new: "b".into(),
+                replace_all: false,
+            },
+            error: Some("nope".into()),
+        };
+        assert_eq!(
+            serde_json::to_value(&attempt).unwrap(),
+            json!({ "tool": "edit", "old": "a", "new": "b", "replace_all": false, "error": "nope" })
+        );
     }
 
     #[test]
@@ -299,10 +694,138 @@ mod tests {
             vec![edit_call("1", "(empty)", "fn main() {}")],
             vec![call("2", "finish")],
         ]);
-        let out = run(&mut m, seeded.to_string(), TURNS).unwrap().text;
+        let out = run(&mut m, seeded.to_string(), &Options::default())
+            .unwrap()
+            .text;
         assert_eq!(out, "fn main() {}");
     }
 
+    #[test]
+    fn re_editing_text_written_this_turn_conflicts() {
+        let mut m = ScriptedModel::new(vec![
+            vec![
+                edit_call("1", "fox", "cat"),
+                edit_call("2", "cat jumps", "cat leaps"),
+                edit_call("3", "dog", "hen"),
+            ],
+            vec![
+                edit_call("4", "cat jumps", "cat leaps"),
+                call("5", "finish"),
+            ],
+        ]);
+        let out = run(
+            &mut m,
+            "the fox jumps over the dog".into(),
+            &Options::default(),
+        )
+        .unwrap();
+        let conflict = m.seen[1][1].result.as_ref().unwrap_err();
+        assert!(conflict.contains("lines 1-1"), "{conflict}");
+        assert!(conflict.contains("\"cat\""), "{conflict}");
+        assert_eq!(m.seen[1][2].result, Ok("ok".into()));
+        assert_eq!(out.text, "the cat leaps over the hen");
+
+        let mut m = ScriptedModel::new(vec![
+            vec![
+                edit_call("1", "fox jumps", "cat leaps"),
+                edit_call("2", "jumps over", "hops over"),
+            ],
+            vec![call("3", "finish")],
+        ]);
+        let out = run(
+            &mut m,
+            "the fox jumps over the dog".into(),
+            &Options::default(),
+        )
+        .unwrap();
+        let conflict = m.seen[1][1].result.as_ref().unwrap_err();
+        assert!(conflict.contains("\"cat leaps\""), "{conflict}");
+        assert_eq!(out.text, "the cat leaps over the dog");
+    }
+
+    #[test]
+    fn malformed_arguments_are_reported_to_the_model() {
+        let garbled = RawCall {
+            id: "1".into(),
+            name: "view".into(),
+            args: arguments("{\"line_numbers\": tru"),
+        };
+        let mut m = ScriptedModel::new(vec![vec![garbled], vec![call("2", "finish")]]);
+        run(&mut m, "text".into(), &Options::default()).unwrap();
+        let err = m.seen[1][0].result.as_ref().unwrap_err();
+        assert!(err.contains("not valid JSON"), "{err}");
+        assert_eq!(arguments(""), json!({}));
+    }
+
+    #[test]
+    fn loose_matches_quote_what_was_replaced() {
+        let mut m = ScriptedModel::new(vec![
+            vec![edit_call("1", "\ta();", "\tb();")],
+            vec![call("2", "finish")],
+        ]);
+        let out = run(
+            &mut m,
+            "fn f() {\n    a();\n}\n".into(),
+            &Options::default(),
+        )
+        .unwrap();
+        let reply = m.seen[1][0].result.as_ref().unwrap();
+        assert!(reply.contains("did not match exactly"), "{reply}");
+        assert!(reply.contains("\n    a();\n"), "{reply}");
+        assert_eq!(out.text, "fn f() {\n    b();\n}\n");
+    }
+
+    #[test]
+    fn finish_is_refused_while_the_syntax_is_broken() {
+        let rust = Options {
+            lang: Some(Lang::Rust),
+            ..Options::default()
+        };
+        let mut m = ScriptedModel::new(vec![
+            vec![edit_call("1", "1;", "1"), call("2", "finish")],
+            vec![edit_call("3", "= 1", "= 1;"), call("4", "finish")],
+        ]);
+        let out = run(&mut m, "fn f() { let x = 1; }\n".into(), &rust).unwrap();
+        let refused = m.seen[1][1].result.as_ref().unwrap_err();
+        assert!(refused.contains("no longer parses as Rust"), "{refused}");
+        assert!(refused.contains("line 1"), "{refused}");
+        assert_eq!(out.text, "fn f() { let x = 1; }\n");
+        assert_eq!(out.turns, 2);
+
+        // Text that didn't parse to begin with isn't held to it, and the retries are bounded.
+        let mut m = ScriptedModel::new(vec![vec![call("1", "finish")]]);
+        assert!(run(&mut m, "fn f( {".into(), &rust).is_ok());
+        let broken = || vec![edit_call("1", "1;", "1"), call("2", "finish")];
+        let mut m = ScriptedModel::new(vec![
+            broken(),
+            vec![call("3", "finish")],
+            vec![call("4", "finish")],
+        ]);
+        let out = run(&mut m, "fn f() { let x = 1; }".into(), &rust).unwrap();
+        assert_eq!(out.text, "fn f() { let x = 1 }");
+        assert_eq!(out.turns, 3);
+        assert!(!out.syntax_errors.is_empty());
+    }
+
+    #[test]
+    fn stopping_without_finish_is_syntax_checked_too() {
+        let rust = Options {
+            lang: Some(Lang::Rust),
+            ..Options::default()
+        };
+        let mut m = ScriptedModel::new(vec![
+            vec![edit_call("1", "1;", "1")],
+            vec![],
+            vec![edit_call("2", "= 1", "= 1;")],
+        ]);
+        let out = run(&mut m, "fn f() { let x = 1; }".into(), &rust).unwrap();
+        assert_eq!(m.follow_ups.len(), 1);
+        assert!(m.follow_ups[0].contains("no longer parses as Rust"));
+        assert_eq!(out.text, "fn f() { let x = 1; }");
+        assert!(out.syntax_errors.is_empty());
+        assert_eq!(out.turns, 4);
+    }
+
     #[test]
     fn parallel_edits_in_one_turn() {
         let mut m = ScriptedModel::new(vec![vec![
@@ -310,14 +833,16 @@ mod tests {
             edit_call("2", "two", "2"),
             call("3", "finish"),
         ]]);
-        let out = run(&mut m, "one two".into(), TURNS).unwrap().text;
+        let out = run(&mut m, "one two".into(), &Options::default())
+            .unwrap()
+            .text;
         assert_eq!(out, "1 2");
     }
 
     #[test]
     fn natural_done_without_finish() {
         let mut m = ScriptedModel::new(vec![vec![edit_call("1", "a", "b")], vec![]]);
-        let out = run(&mut m, "a".into(), TURNS).unwrap().text;
+        let out = run(&mut m, "a".into(), &Options::default()).unwrap().text;
         assert_eq!(out, "b");
     }
 
@@ -327,12 +852,39 @@ mod tests {
             vec![edit_call("1", "nope", "x")],
             vec![edit_call("2", "a", "b"), call("3", "finish")],
         ]);
-        let out = run(&mut m, "a".into(), TURNS).unwrap().text;
+        let out = run(&mut m, "a".into(), &Options::default()).unwrap().text;
         assert_eq!(out, "b");
         let err = m.seen[1][0].result.as_ref().unwrap_err();
         assert!(err.contains("could not find"));
     }
 
+    #[test]
+    fn finish_leaves_its_turn_pending_for_a_follow_up() {
+        let mut m = ScriptedModel::new(vec![vec![
+            edit_call("1", "a", "b"),
+            call("2", "finish"),
+            call("3", "view"),
+        ]]);
+        let out = run(&mut m, "a".into(), &Options::default()).unwrap();
+        let ids: Vec<&str> = out.pending.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, ["1", "2", "3"]);
+        assert_eq!(out.pending[1].result, Ok("finished".to_string()));
+        assert!(out.pending[2].result.is_err());
+    }
+
+    #[test]
+    fn resume_continues_from_the_previous_text() {
+        let mut m = ScriptedModel::new(vec![
+            vec![edit_call("1", "a", "b"), call("2", "finish")],
+            vec![edit_call("3", "b", "c"), call("4", "finish")],
+        ]);
+        let first = run(&mut m, "a".into(), &Options::default()).unwrap();
+        m.follow_up(first.pending, "again").unwrap();
+        let second = resume(&mut m, "a", first.text, &Options::default()).unwrap();
+        assert_eq!(second.text, "c");
+        assert_eq!(m.seen[1].len(), 2);
+    }
+
     #[test]
     fn view_returns_current_buffer() {
         let mut m = ScriptedModel::new(vec![
@@ -340,7 +892,7 @@ mod tests {
             vec![call("2", "view")],
             vec![call("3", "finish")],
         ]);
-        let out = run(&mut m, "a".into(), TURNS).unwrap().text;
+        let out = run(&mut m, "a".into(), &Options::default()).unwrap().text;
         assert_eq!(out, "b");
         assert_eq!(m.seen[2][0].result, Ok("b".to_string()));
     }
@@ -352,7 +904,7 @@ mod tests {
             vec![call("2", "reset")],
             vec![call("3", "finish")],
         ]);
-        let out = run(&mut m, "a".into(), TURNS).unwrap().text;
+        let out = run(&mut m, "a".into(), &Options::default()).unwrap().text;
         assert_eq!(out, "a");
         assert_eq!(m.seen[2][0].result, Ok("a".to_string()));
     }
@@ -363,7 +915,7 @@ mod tests {
             vec![call("1", "frobnicate")],
             vec![call("2", "finish")],
         ]);
-        let out = run(&mut m, "x".into(), TURNS).unwrap().text;
+        let out = run(&mut m, "x".into(), &Options::default()).unwrap().text;
         assert_eq!(out, "x");
         let err = m.seen[1][0].result.as_ref().unwrap_err();
         assert!(err.contains("unknown tool"));
@@ -376,7 +928,7 @@ mod tests {
             vec![edit_call("2", "nope", "x")],
             vec![edit_call("3", "nope", "x")],
         ]);
-        let err = run(&mut m, "a".into(), TURNS).unwrap_err();
+        let err = run(&mut m, "a".into(), &Options::default()).unwrap_err();
         assert!(err.to_string().contains("consecutive"));
     }
 
@@ -388,7 +940,7 @@ mod tests {
             vec![edit_call("3", "nope", "x")],
             vec![edit_call("4", "a", "b"), call("5", "finish")],
         ]);
-        let out = run(&mut m, "a".into(), TURNS).unwrap().text;
+        let out = run(&mut m, "a".into(), &Options::default()).unwrap().text;
         assert_eq!(out, "b");
     }
 
@@ -398,7 +950,15 @@ mod tests {
             .map(|i| vec![call(&i.to_string(), "view")])
             .collect();
         let mut m = ScriptedModel::new(turns);
-        let err = run(&mut m, "x".into(), 5).unwrap_err();
+        let err = run(
+            &mut m,
+            "x".into(),
+            &Options {
+                max_turns: 5,
+                ..Options::default()
+            },
+        )
+        .unwrap_err();
         assert!(err.to_string().contains("limit"));
     }
 }
@@ -2,9 +2,13 @@ use schemars::Schema;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 
-use crate::agent::{Model, RawCall, Seed, Tool, ToolResult, SEED_CALL_ID, SEED_TOOL};
+use crate::agent::{Model, RawCall, Seed, Tool, ToolResult, Usage, SEED_CALL_ID, SEED_TOOL};
+use crate::backend::{stream_ended_early, stream_error, ApiError, ErrorKind, Http, SseEvent};
+use crate::config_files::Config;
 
-const MAX_TOKENS: u32 = 80000;
+pub(crate) const MAX_TOKENS: u32 = 80000;
+// The smallest thinking budget the API accepts.
+pub(crate) const MIN_THINKING_BUDGET: u32 = 1024;
 
 const API_URL: &str = "https://api.anthropic.com/v1/messages";
 const ANTHROPIC_VERSION: &str = "2023-06-01";
@@ -14,6 +18,15 @@ struct SystemBlock {
     #[serde(rename = "type")]
     kind: TextType,
     text: String,
+    cache_control: CacheControl,
+}
+
+// Marks the end of a prefix the API may cache. Everything up to and including the system
+// prompt, the tool definitions and the seeded selection is identical on every turn.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CacheControl {
+    Ephemeral,
 }
 
 #[derive(Serialize)]
@@ -22,20 +35,24 @@ enum TextType {
     Text,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 enum ContentBlock {
     Text {
         text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
     },
     ToolResult {
         tool_use_id: String,
         content: String,
         is_error: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
     },
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(tag = "role", rename_all = "snake_case")]
 enum Message {
     User { content: Vec<ContentBlock> },
@@ -68,11 +85,19 @@ enum AssistantBlock {
     },
 }
 
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Thinking {
+    Enabled { budget_tokens: u32 },
+}
+
 #[derive(Serialize)]
 struct ToolDef {
     name: String,
     description: String,
     input_schema: Schema,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
 }
 
 #[derive(Serialize)]
@@ -87,10 +112,12 @@ enum ToolChoice {
 pub struct AnthropicAgent {
     key: String,
     model: String,
-    client: reqwest::blocking::Client,
+    http: Http,
     system: Vec<SystemBlock>,
     messages: Vec<Message>,
     tools: Vec<ToolDef>,
+    thinking_budget: Option<u32>,
+    usage: Usage,
 }
 
 #[derive(Serialize)]
@@ -102,18 +129,38 @@ struct Request<'a> {
     tool_choice: ToolChoice,
     #[serde(skip_serializing_if = "<[_]>::is_empty")]
     system: &'a [SystemBlock],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<Thinking>,
+    stream: bool,
 }
 
 impl AnthropicAgent {
-    pub fn new(key: String, model: String, seed: &Seed, tools: &[Tool]) -> Self {
-        let system = vec![SystemBlock {
-            kind: TextType::Text,
-            text: seed.system.to_string(),
-        }];
+    pub fn new(key: String, model: String, config: &Config, seed: &Seed, tools: &[Tool]) -> Self {
+        if thinking_budget(config).is_some() {
+            // With thinking on, an assistant turn that calls a tool has to open with a
+            // thinking block, which the seeded view call can't have. Send the text instead.
+            let messages = vec![Message::User {
+                content: vec![
+                    ContentBlock::Text {
+                        text: seed.transform.to_string(),
+                        cache_control: None,
+                    },
+                    ContentBlock::Text {
+                        text: format!(
+                            "The selected text, as `{SEED_TOOL}` returns it:\n\n{}",
+                            seed.selected
+                        ),
+                        cache_control: Some(CacheControl::Ephemeral),
+                    },
+                ],
+            }];
+            return Self::with_messages(key, model, config, seed.system, messages, tools);
+        }
         let messages = vec![
             Message::User {
                 content: vec![ContentBlock::Text {
                     text: seed.transform.to_string(),
+                    cache_control: None,
                 }],
             },
             Message::Assistant {
@@ -129,24 +176,60 @@ impl AnthropicAgent {
                     tool_use_id: SEED_CALL_ID.to_string(),
                     content: seed.selected.to_string(),
                     is_error: false,
+                    cache_control: Some(CacheControl::Ephemeral),
                 }],
             },
         ];
+        Self::with_messages(key, model, config, seed.system, messages, tools)
+    }
+
+    pub fn resume(
+        key: String,
+        model: String,
+        config: &Config,
+        system: &str,
+        transcript: Value,
+        tools: &[Tool],
+    ) -> anyhow::Result<Self> {
+        let messages = serde_json::from_value(transcript)
+            .map_err(|e| anyhow::anyhow!("saved Anthropic conversation did not parse: {e}"))?;
+        Ok(Self::with_messages(
+            key, model, config, system, messages, tools,
+        ))
+    }
+
+    fn with_messages(
+        key: String,
+        model: String,
+        config: &Config,
+        system: &str,
+        messages: Vec<Message>,
+        tools: &[Tool],
+    ) -> Self {
+        let system = vec![SystemBlock {
+            kind: TextType::Text,
+            text: system.to_string(),
+            cache_control: CacheControl::Ephemeral,
+        }];
         let tools = tools
             .iter()
-            .map(|t| ToolDef {
+            .enumerate()
+            .map(|(i, t)| ToolDef {
                 name: t.name.to_string(),
                 description: t.description.to_string(),
                 input_schema: t.input_schema.clone(),
+                cache_control: (i + 1 == tools.len()).then_some(CacheControl::Ephemeral),
             })
             .collect();
         AnthropicAgent {
             key,
             model,
-            client: crate::backend::http_client(),
+            http: Http::new(config),
             system,
             messages,
             tools,
+            thinking_budget: thinking_budget(config),
+            usage: Usage::default(),
         }
     }
 
@@ -158,31 +241,45 @@ impl AnthropicAgent {
             tools: &self.tools,
             tool_choice: ToolChoice::Auto,
             system: &self.system,
+            thinking: self
+                .thinking_budget
+                .map(|budget_tokens| Thinking::Enabled { budget_tokens }),
+            stream: true,
         }
     }
 }
 
+fn thinking_budget(config: &Config) -> Option<u32> {
+    config.thinking_budget.filter(|&b| b > 0)
+}
+
+fn tool_results(results: Vec<ToolResult>) -> Vec<ContentBlock> {
+    results
+        .into_iter()
+        .map(|r| {
+            let (content, is_error) = match r.result {
+                Ok(c) => (c, false),
+                Err(c) => (c, true),
+            };
+            ContentBlock::ToolResult {
+                tool_use_id: r.id,
+                content,
+                is_error,
+                cache_control: None,
+            }
+        })
+        .collect()
+}
+
 impl Model for AnthropicAgent {
     fn turn(&mut self, results: Vec<ToolResult>) -> anyhow::Result<Vec<RawCall>> {
         if !results.is_empty() {
-            let content = results
-                .into_iter()
-                .map(|r| {
-                    let (content, is_error) = match r.result {
-                        Ok(c) => (c, false),
-                        Err(c) => (c, true),
-                    };
-                    ContentBlock::ToolResult {
-                        tool_use_id: r.id,
-                        content,
-                        is_error,
-                    }
-                })
-                .collect();
+            let content = tool_results(results);
             self.messages.push(Message::User { content });
         }
 
-        let body = post(&self.client, &self.key, &self.request())?;
+        let body = post(&self.http, &self.key, &self.request())?;
+        self.usage += usage_from_body(&body);
         let content = body
             .get("content")
             .cloned()
@@ -193,6 +290,34 @@ impl Model for AnthropicAgent {
         self.messages.push(Message::Assistant { content });
         Ok(calls)
     }
+
+    fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    fn transcript(&self) -> anyhow::Result<Value> {
+        Ok(serde_json::to_value(&self.messages)?)
+    }
+
+    fn follow_up(&mut self, results: Vec<ToolResult>, transform: &str) -> anyhow::Result<()> {
+        let mut content = tool_results(results);
+        content.push(ContentBlock::Text {
+            text: transform.to_string(),
+            cache_control: None,
+        });
+        self.messages.push(Message::User { content });
+        Ok(())
+    }
+}
+
+fn usage_from_body(body: &Value) -> Usage {
+    let count = |k: &str| body["usage"][k].as_u64().unwrap_or(0);
+    Usage {
+        input_tokens: count("input_tokens"),
+        output_tokens: count("output_tokens"),
+        cache_read_tokens: count("cache_read_input_tokens"),
+        cache_write_tokens: count("cache_creation_input_tokens"),
+    }
 }
 
 fn calls_from_content(content: &[AssistantBlock]) -> Vec<RawCall> {
@@ -211,20 +336,112 @@ fn calls_from_content(content: &[AssistantBlock]) -> Vec<RawCall> {
         .collect()
 }
 
-fn post(client: &reqwest::blocking::Client, key: &str, req: &Request) -> anyhow::Result<Value> {
+fn post(http: &Http, key: &str, req: &Request) -> anyhow::Result<Value> {
     tracing::debug!(
         "anthropic request: {}",
         serde_json::to_value(req).unwrap_or_default()
     );
-    crate::backend::send_json(
-        client
-            .post(API_URL)
+    http.stream_sse::<Stream>(
+        http.post(API_URL)
             .header("x-api-key", key)
             .header("anthropic-version", ANTHROPIC_VERSION)
             .json(req),
     )
 }
 
+#[derive(Default)]
+struct Stream {
+    content: Vec<Value>,
+    partial_json: Vec<String>,
+    usage: Map<String, Value>,
+    done: bool,
+}
+
+impl crate::backend::Stream for Stream {
+    fn event(&mut self, event: SseEvent) -> Result<(), ApiError> {
+        let Ok(data) = serde_json::from_str::<Value>(&event.data) else {
+            return Ok(());
+        };
+        let index = data["index"].as_u64().unwrap_or(0) as usize;
+        match data["type"].as_str().unwrap_or(&event.event) {
+            "message_start" => merge(&mut self.usage, &data["message"]["usage"]),
+            "message_delta" => merge(&mut self.usage, &data["usage"]),
+            "message_stop" => self.done = true,
+            "content_block_start" => {
+                let mut block = data["content_block"].clone();
+                if block["type"] == "tool_use" {
+                    crate::progress::tool(block["name"].as_str().unwrap_or_default());
+                }
+                if let Some(slot) = block.get_mut("input") {
+                    *slot = Value::Object(Map::new());
+                }
+                self.content.resize(index, Value::Null);
+                self.content.push(block);
+                self.partial_json.resize(index + 1, String::new());
+            }
+            "content_block_delta" => {
+                let Some(block) = self.content.get_mut(index) else {
+                    return Ok(());
+                };
+                let delta = &data["delta"];
+                let field = match delta["type"].as_str() {
+                    Some("text_delta") => "text",
+                    Some("thinking_delta") => "thinking",
+                    Some("signature_delta") => "signature",
+                    Some("input_json_delta") => {
+                        let partial = delta["partial_json"].as_str().unwrap_or_default();
+                        self.partial_json[index].push_str(partial);
+                        return Ok(());
+                    }
+                    _ => return Ok(()),
+                };
+                let piece = delta[field].as_str().unwrap_or_default();
+                let text = format!("{}{piece}", block[field].as_str().unwrap_or_default());
+                block[field] = Value::String(text);
+            }
+            "content_block_stop" => {
+                let (Some(partial), Some(block)) = (
+                    self.partial_json.get_mut(index),
+                    self.content.get_mut(index),
+                ) else {
+                    // A broken stream, so retried like one that ended early.
+                    return Err(ApiError {
+                        kind: ErrorKind::Network,
+                        status: None,
+                        retry_after: None,
+                        body: format!("stream stopped content block {index} before starting it"),
+                    });
+                };
+                let partial = std::mem::take(partial);
+                if !partial.is_empty() {
+                    block["input"] = serde_json::from_str(&partial).map_err(|e| ApiError {
+                        kind: ErrorKind::Other,
+                        status: None,
+                        retry_after: None,
+                        body: format!("streamed tool input was not JSON ({e}): {partial}"),
+                    })?;
+                }
+            }
+            "error" => return Err(stream_error(&data["error"])),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, ApiError> {
+        if !self.done {
+            return Err(stream_ended_early());
+        }
+        Ok(serde_json::json!({ "content": self.content, "usage": self.usage }))
+    }
+}
+
+fn merge(into: &mut Map<String, Value>, from: &Value) {
+    if let Some(from) = from.as_object() {
+        into.extend(from.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -258,7 +475,13 @@ mod tests {
             selected: "selected",
             transform: "transform",
         };
-        let agent = AnthropicAgent::new("k".into(), "claude-opus-4-8".into(), &seed, &tools);
+        let agent = AnthropicAgent::new(
+            "k".into(),
+            "claude-opus-4-8".into(),
+            &Config::default(),
+            &seed,
+            &tools,
+        );
         let req = request_json(&agent);
 
         assert_eq!(req["system"][0]["type"], "text");
@@ -282,7 +505,17 @@ mod tests {
             .iter()
             .map(|t| t["name"].as_str().unwrap())
             .collect();
-        assert_eq!(names, ["edit", "view", "reset", "finish"]);
+        assert_eq!(
+            names,
+            [
+                "edit",
+                "edit_lines",
+                "regex_replace",
+                "view",
+                "reset",
+                "finish"
+            ]
+        );
     }
 
     #[test]
@@ -293,12 +526,14 @@ mod tests {
             selected: "selected",
             transform: "transform",
         };
-        let mut agent = AnthropicAgent::new("k".into(), "m".into(), &seed, &tools);
+        let mut agent =
+            AnthropicAgent::new("k".into(), "m".into(), &Config::default(), &seed, &tools);
         agent.messages.push(Message::User {
             content: vec![ContentBlock::ToolResult {
                 tool_use_id: "tu_1".into(),
                 content: "ok".into(),
                 is_error: false,
+                cache_control: None,
             }],
         });
         let req = request_json(&agent);
@@ -306,6 +541,7 @@ mod tests {
         assert_eq!(req["messages"][3]["role"], "user");
         assert_eq!(block["type"], "tool_result");
         assert_eq!(block["tool_use_id"], "tu_1");
+        assert!(block.get("cache_control").is_none());
         assert_eq!(block["content"], "ok");
         assert_eq!(block["is_error"], false);
     }
@@ -318,7 +554,8 @@ mod tests {
             selected: "selected",
             transform: "transform",
         };
-        let mut agent = AnthropicAgent::new("k".into(), "m".into(), &seed, &tools);
+        let mut agent =
+            AnthropicAgent::new("k".into(), "m".into(), &Config::default(), &seed, &tools);
         let raw = json!([
             { "type": "thinking", "thinking": "hmm", "signature": "sig" },
             { "type": "tool_use", "id": "tu_1", "name": "edit", "input": { "old": "a", "new": "b" } }
@@ -330,6 +567,48 @@ mod tests {
         assert_eq!(req["messages"][3]["content"], raw);
     }
 
+    #[test]
+    fn resumed_transcript_round_trips_and_takes_a_follow_up() {
+        let tools = crate::agent::tools();
+        let seed = Seed {
+            system: "SYS",
+            selected: "selected",
+            transform: "transform",
+        };
+        let mut agent =
+            AnthropicAgent::new("k".into(), "m".into(), &Config::default(), &seed, &tools);
+        let content: Vec<AssistantBlock> = serde_json::from_value(json!([
+            { "type": "tool_use", "id": "tu_1", "name": "finish", "input": {} }
+        ]))
+        .unwrap();
+        agent.messages.push(Message::Assistant { content });
+        let before = request_json(&agent);
+
+        let transcript = agent.transcript().unwrap();
+        let mut resumed = AnthropicAgent::resume(
+            "k".into(),
+            "m".into(),
+            &Config::default(),
+            "SYS",
+            transcript,
+            &tools,
+        )
+        .unwrap();
+        assert_eq!(request_json(&resumed), before);
+
+        let results = vec![ToolResult {
+            id: "tu_1".into(),
+            result: Ok("finished".into()),
+        }];
+        resumed.follow_up(results, "more").unwrap();
+        let req = request_json(&resumed);
+        assert_eq!(req["messages"][4]["role"], "user");
+        assert_eq!(req["messages"][4]["content"][0]["type"], "tool_result");
+        assert_eq!(req["messages"][4]["content"][0]["tool_use_id"], "tu_1");
+        assert_eq!(req["messages"][4]["content"][1]["type"], "text");
+        assert_eq!(req["messages"][4]["content"][1]["text"], "more");
+    }
+
     #[test]
     fn parses_tool_use_blocks() {
         let content: Vec<AssistantBlock> = serde_json::from_value(json!([
@@ -347,10 +626,208 @@ mod tests {
         assert_eq!(calls[1].name, "finish");
     }
 
+    #[test]
+    fn usage_is_read_from_the_response() {
+        let body = json!({ "usage": { "input_tokens": 12, "output_tokens": 3 } });
+        assert_eq!(
+            usage_from_body(&body),
+            Usage {
+                input_tokens: 12,
+                output_tokens: 3,
+                ..Usage::default()
+            }
+        );
+        assert_eq!(usage_from_body(&json!({})), Usage::default());
+    }
+
     #[test]
     fn no_tool_use_is_no_calls() {
         let content: Vec<AssistantBlock> =
             serde_json::from_value(json!([{ "type": "text", "text": "all done" }])).unwrap();
         assert!(calls_from_content(&content).is_empty());
     }
+
+    #[test]
+    fn streamed_events_rebuild_the_response_body() {
+        use crate::backend::Stream as _;
+
+        let events = [
+            json!({ "type": "message_start", "message": { "usage": { "input_tokens": 10, "output_tokens": 1 } } }),
+            json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "thinking", "thinking": "" } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "thinking_delta", "thinking": "hm" } }),
+            json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "signature_delta", "signature": "sig" } }),
+            json!({ "type": "content_block_stop", "index": 0 }),
+            json!({ "type": "content_block_start", "index": 1, "content_block": { "type": "text", "text": "" } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "text_delta", "text": "on " } }),
+            json!({ "type": "content_block_delta", "index": 1, "delta": { "type": "text_delta", "text": "it" } }),
+            json!({ "type": "content_block_stop", "index": 1 }),
+            json!({ "type": "content_block_start", "index": 2, "content_block": { "type": "tool_use", "id": "tu_1", "name": "edit", "input": {} } }),
+            json!({ "type": "content_block_delta", "index": 2, "delta": { "type": "input_json_delta", "partial_json": "{\"old\":\"a\"," } }),
+            json!({ "type": "content_block_delta", "index": 2, "delta": { "type": "input_json_delta", "partial_json": "\"new\":\"b\"}" } }),
+            json!({ "type": "content_block_stop", "index": 2 }),
+            json!({ "type": "content_block_start", "index": 3, "content_block": { "type": "tool_use", "id": "tu_2", "name": "finish", "input": {} } }),
+            json!({ "type": "content_block_stop", "index": 3 }),
+            json!({ "type": "message_delta", "usage": { "output_tokens": 7 } }),
+        ];
+        let mut stream = Stream::default();
+        for e in &events {
+            let event = SseEvent {
+                event: e["type"].as_str().unwrap().into(),
+                data: e.to_string(),
+            };
+            stream.event(event).unwrap();
+        }
+        let early = Stream {
+            content: stream.content.clone(),
+            ..Stream::default()
+        };
+        assert!(early.finish().is_err());
+        stream
+            .event(SseEvent {
+                event: "message_stop".into(),
+                data: r#"{"type":"message_stop"}"#.into(),
+            })
+            .unwrap();
+
+        let body = stream.finish().unwrap();
+        assert_eq!(
+            body["content"],
+            json!([
+                { "type": "thinking", "thinking": "hm", "signature": "sig" },
+                { "type": "text", "text": "on it" },
+                { "type": "tool_use", "id": "tu_1", "name": "edit", "input": { "old": "a", "new": "b" } },
+                { "type": "tool_use", "id": "tu_2", "name": "finish", "input": {} }
+            ])
+        );
+        assert_eq!(
+            usage_from_body(&body),
+            Usage {
+                input_tokens: 10,
+                output_tokens: 7,
+                ..Usage::default()
+            }
+        );
+    }
+
+    #[test]
+    fn stream_errors_are_surfaced() {
+        use crate::backend::Stream as _;
+
+        let mut stream = Stream::default();
+        let err = stream
+            .event(SseEvent {
+                event: "error".into(),
+                data:
+                    r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#
+                        .into(),
+            })
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Overloaded);
+    }
+
+    #[test]
+    fn stopping_a_block_that_never_started_is_an_error() {
+        use crate::backend::Stream as _;
+
+        let err = Stream::default()
+            .event(SseEvent {
+                event: "content_block_stop".into(),
+                data: r#"{"type":"content_block_stop","index":2}"#.into(),
+            })
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Network);
+    }
+
+    #[test]
+    fn stable_prefix_is_marked_for_caching() {
+        let tools = crate::agent::tools();
+        let seed = Seed {
+            system: "SYS",
+            selected: "selected",
+            transform: "transform",
+        };
+        let agent = AnthropicAgent::new("k".into(), "m".into(), &Config::default(), &seed, &tools);
+        let req = request_json(&agent);
+        let ephemeral = json!({ "type": "ephemeral" });
+
+        assert_eq!(req["system"][0]["cache_control"], ephemeral);
+        let tool_defs = req["tools"].as_array().unwrap();
+        let (last, rest) = tool_defs.split_last().unwrap();
+        assert_eq!(last["cache_control"], ephemeral);
+        assert!(rest.iter().all(|t| t.get("cache_control").is_none()));
+        assert_eq!(req["messages"][2]["content"][0]["cache_control"], ephemeral);
+    }
+
+    #[test]
+    fn cache_usage_is_read_from_the_response() {
+        let body = json!({ "usage": {
+            "input_tokens": 5, "output_tokens": 2,
+            "cache_read_input_tokens": 900, "cache_creation_input_tokens": 40
+        } });
+        assert_eq!(
+            usage_from_body(&body),
+            Usage {
+                input_tokens: 5,
+                output_tokens: 2,
+                cache_read_tokens: 900,
+                cache_write_tokens: 40,
+            }
+        );
+    }
+
+    #[test]
+    fn thinking_is_off_unless_budgeted() {
+        let tools = crate::agent::tools();
+        let seed = Seed {
+            system: "SYS",
+            selected: "selected",
+            transform: "transform",
+        };
+        let with_budget = |thinking_budget| {
+            let config = Config {
+                thinking_budget,
+                ..Config::default()
+            };
+            let agent = AnthropicAgent::new("k".into(), "m".into(), &config, &seed, &tools);
+            request_json(&agent)
+        };
+        assert!(with_budget(None).get("thinking").is_none());
+        assert!(with_budget(Some(0)).get("thinking").is_none());
+        assert_eq!(
+            with_budget(Some(4096))["thinking"],
+            json!({ "type": "enabled", "budget_tokens": 4096 })
+        );
+    }
+
+    #[test]
+    fn thinking_sends_the_selection_as_user_text() {
+        let tools = crate::agent::tools();
+        let seed = Seed {
+            system: "SYS",
+            selected: "selected",
+            transform: "transform",
+        };
+        let config = Config {
+            thinking_budget: Some(4096),
+            ..Config::default()
+        };
+        let agent = AnthropicAgent::new("k".into(), "m".into(), &config, &seed, &tools);
+        let req = request_json(&agent);
+        let messages = req["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["role"], "user");
+        assert_eq!(
+            messages[0]["content"][0],
+            json!({ "type": "text", "text": "transform" })
+        );
+        assert_eq!(
+            messages[0]["content"][1],
+            json!({
+                "type": "text",
+                "text": "The selected text, as `view` returns it:\n\nselected",
+                "cache_control": { "type": "ephemeral" },
+            })
+        );
+        assert!(!req.to_string().contains("tool_use"));
+    }
 }
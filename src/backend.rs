@@ -1,15 +1,32 @@
+use std::io::{BufRead, BufReader};
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
 use serde_json::Value;
 
-use crate::agent::{Model, Seed, Tool};
+use crate::agent::{Model, Seed, Tool, ToolResult};
 use crate::anthropic::AnthropicAgent;
-use crate::config_files::{Provider, Secrets};
+use crate::cassette::{Cassette, Mode};
+use crate::config_files::{Config, OpenaiApi, Provider, Secrets};
+use crate::gemini::GeminiAgent;
+use crate::ollama::OllamaAgent;
 use crate::openai::OpenaiAgent;
+use crate::openai_responses::ResponsesAgent;
 
-fn key_for(provider: Provider, secrets: &Secrets) -> Result<String> {
+fn key_for(provider: Provider, config: &Config, secrets: &Secrets) -> Result<String> {
+    let key = required_key(provider, config, secrets);
+    match config.cassette {
+        Some(Mode::Replay(_)) => Ok(key.unwrap_or_default()),
+        _ => key,
+    }
+}
+
+fn required_key(provider: Provider, config: &Config, secrets: &Secrets) -> Result<String> {
     match provider {
+        Provider::Openai if config.base_url.is_some() => {
+            Ok(secrets.openai_api_key.clone().unwrap_or_default())
+        }
         Provider::Anthropic => secrets.anthropic_api_key.clone().ok_or_else(|| {
             anyhow::anyhow!(
                 "No Anthropic API key found. Set ANTHROPIC_API_KEY or run 'refac login'."
@@ -18,44 +35,628 @@ fn key_for(provider: Provider, secrets: &Secrets) -> Result<String> {
         Provider::Openai => secrets.openai_api_key.clone().ok_or_else(|| {
             anyhow::anyhow!("No OpenAI API key found. Set OPENAI_API_KEY or run 'refac login'.")
         }),
+        Provider::Ollama => Ok(String::new()),
+        Provider::Gemini => secrets.gemini_api_key.clone().ok_or_else(|| {
+            anyhow::anyhow!("No Gemini API key found. Set GEMINI_API_KEY or run 'refac login'.")
+        }),
     }
 }
 
 pub fn resolve_agent(
     provider: Provider,
     model: &str,
+    config: &Config,
     secrets: &Secrets,
     seed: &Seed,
     tools: &[Tool],
 ) -> Result<Box<dyn Model>> {
-    let key = key_for(provider, secrets)?;
+    let key = key_for(provider, config, secrets)?;
+    Ok(match provider {
+        Provider::Anthropic => Box::new(AnthropicAgent::new(
+            key,
+            model.to_string(),
+            config,
+            seed,
+            tools,
+        )),
+        Provider::Openai => match config.openai_api.unwrap_or_default() {
+            OpenaiApi::Chat => Box::new(OpenaiAgent::new(
+                key,
+                model.to_string(),
+                config,
+                seed,
+                tools,
+            )),
+            OpenaiApi::Responses => Box::new(ResponsesAgent::new(
+                key,
+                model.to_string(),
+                config,
+                seed,
+                tools,
+            )),
+        },
+        Provider::Ollama => Box::new(OllamaAgent::new(model.to_string(), config, seed, tools)),
+        Provider::Gemini => Box::new(GeminiAgent::new(
+            key,
+            model.to_string(),
+            config,
+            seed,
+            tools,
+        )),
+    })
+}
+
+pub fn resume_agent(
+    provider: Provider,
+    model: &str,
+    config: &Config,
+    secrets: &Secrets,
+    system: &str,
+    transcript: Value,
+    tools: &[Tool],
+) -> Result<Box<dyn Model>> {
+    let key = key_for(provider, config, secrets)?;
     Ok(match provider {
-        Provider::Anthropic => Box::new(AnthropicAgent::new(key, model.to_string(), seed, tools)),
-        Provider::Openai => Box::new(OpenaiAgent::new(key, model.to_string(), seed, tools)),
+        Provider::Anthropic => Box::new(AnthropicAgent::resume(
+            key,
+            model.to_string(),
+            config,
+            system,
+            transcript,
+            tools,
+        )?),
+        Provider::Openai => match config.openai_api.unwrap_or_default() {
+            OpenaiApi::Chat => Box::new(OpenaiAgent::resume(
+                key,
+                model.to_string(),
+                config,
+                transcript,
+                tools,
+            )?),
+            OpenaiApi::Responses => Box::new(ResponsesAgent::resume(
+                key,
+                model.to_string(),
+                config,
+                system,
+                transcript,
+                tools,
+            )?),
+        },
+        Provider::Ollama => Box::new(OllamaAgent::resume(
+            model.to_string(),
+            config,
+            transcript,
+            tools,
+        )?),
+        Provider::Gemini => Box::new(GeminiAgent::resume(
+            key,
+            model.to_string(),
+            config,
+            system,
+            transcript,
+            tools,
+        )?),
     })
 }
 
-pub fn http_client() -> reqwest::blocking::Client {
-    reqwest::blocking::Client::builder()
-        .timeout(Duration::from_secs(60 * 4))
-        .build()
-        .expect("building HTTP client")
+const DEFAULT_MAX_RETRIES: u32 = 4;
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    RateLimited,
+    Overloaded,
+    Server,
+    Network,
+    Auth,
+    BadRequest,
+    ContextTooLong,
+    Other,
+}
+
+impl ErrorKind {
+    fn retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::RateLimited | ErrorKind::Overloaded | ErrorKind::Server | ErrorKind::Network
+        )
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            ErrorKind::RateLimited => "rate limited",
+            ErrorKind::Overloaded => "provider overloaded",
+            ErrorKind::Server => "provider server error",
+            ErrorKind::Network => "network error",
+            ErrorKind::Auth => "authentication failed, check your API key",
+            ErrorKind::BadRequest => "request rejected",
+            ErrorKind::ContextTooLong => "input is too long for the model's context window",
+            ErrorKind::Other => "request failed",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub kind: ErrorKind,
+    pub status: Option<u16>,
+    pub retry_after: Option<Duration>,
+    pub body: String,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind.describe())?;
+        if let Some(status) = self.status {
+            write!(f, ". Status: {status}")?;
+        }
+        write!(f, ". Body: {}", self.body)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+fn classify(status: u16, body: &str) -> ErrorKind {
+    let lower = body.to_lowercase();
+    let too_long = [
+        "context_length_exceeded",
+        "context length",
+        "context window",
+        "prompt is too long",
+        "too many tokens",
+    ]
+    .iter()
+    .any(|s| lower.contains(s));
+    match status {
+        429 => ErrorKind::RateLimited,
+        503 | 529 => ErrorKind::Overloaded,
+        _ if lower.contains("overloaded") && status >= 500 => ErrorKind::Overloaded,
+        408 | 500..=599 => ErrorKind::Server,
+        401 | 403 => ErrorKind::Auth,
+        413 => ErrorKind::ContextTooLong,
+        400..=499 if too_long => ErrorKind::ContextTooLong,
+        400..=499 => ErrorKind::BadRequest,
+        _ => ErrorKind::Other,
+    }
 }
 
-pub fn send_json(request: reqwest::blocking::RequestBuilder) -> Result<Value> {
-    let response = request.send().context("sending request")?;
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let header = |name| headers.get(name)?.to_str().ok()?.trim().parse::<f64>().ok();
+    let delay = match header("retry-after-ms") {
+        Some(ms) => ms / 1000.0,
+        None => header("retry-after")?,
+    };
+    Duration::try_from_secs_f64(delay).ok()
+}
+
+pub struct Http {
+    client: reqwest::blocking::Client,
+    max_retries: u32,
+    base_delay: Duration,
+    cassette: Option<Arc<Cassette>>,
+}
+
+impl Http {
+    pub fn new(config: &Config) -> Self {
+        // The blocking client applies `timeout` to each read of a response body, so for
+        // streamed responses it only trips when the model goes quiet, not on long answers.
+        Http {
+            client: reqwest::blocking::Client::builder()
+                .connect_timeout(Duration::from_secs(30))
+                .timeout(Duration::from_secs(60 * 4))
+                .build()
+                .expect("building HTTP client"),
+            max_retries: config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            base_delay: Duration::from_secs(1),
+            cassette: config.cassette.clone().map(Cassette::shared),
+        }
+    }
+
+    pub fn post(&self, url: &str) -> reqwest::blocking::RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn send_json(&self, request: reqwest::blocking::RequestBuilder) -> Result<Value> {
+        self.through_cassette(request, |request| self.send_json_live(request))
+    }
+
+    fn send_json_live(&self, request: reqwest::blocking::RequestBuilder) -> Result<Value> {
+        self.retrying(request, |response| {
+            let status = response.status();
+            let body = response.text().map_err(network)?;
+            serde_json::from_str(&body).map_err(|_| ApiError {
+                kind: ErrorKind::Other,
+                status: Some(status.as_u16()),
+                retry_after: None,
+                body: format!("response body was not JSON: {body}"),
+            })
+        })
+    }
+
+    // A stream that fails part way through with a retryable error is restarted from
+    // scratch, so the accumulator is rebuilt on every try.
+    pub fn stream_sse<S: Stream>(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+    ) -> Result<Value> {
+        self.through_cassette(request, |request| {
+            self.retrying(request, |response| {
+                let mut stream = S::default();
+                read_sse(BufReader::new(response), |event| stream.event(event))?;
+                stream.finish()
+            })
+        })
+    }
+
+    // Cassettes hold the request body and the response as the backend sees it, after
+    // any stream has been reassembled.
+    fn through_cassette(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+        live: impl FnOnce(reqwest::blocking::RequestBuilder) -> Result<Value>,
+    ) -> Result<Value> {
+        let Some(cassette) = &self.cassette else {
+            return live(request);
+        };
+        let built = request
+            .try_clone()
+            .context("request body can't be recorded")?
+            .build()?;
+        let body = built.body().and_then(|b| b.as_bytes()).unwrap_or_default();
+        let body = serde_json::from_slice(body).unwrap_or(Value::Null);
+        cassette.exchange(built.url().as_str(), body, || live(request))
+    }
+
+    fn retrying<T>(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+        mut read: impl FnMut(reqwest::blocking::Response) -> Result<T, ApiError>,
+    ) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            let this_try = request
+                .try_clone()
+                .context("request body can't be retried")?;
+            match send_once(this_try).and_then(&mut read) {
+                Ok(body) => return Ok(body),
+                Err(e) if e.kind.retryable() && attempt < self.max_retries => {
+                    let delay = self.delay(attempt, e.retry_after);
+                    attempt += 1;
+                    crate::progress::clear();
+                    tracing::warn!(
+                        "{e}; retrying in {} ({attempt}/{})",
+                        humantime::format_duration(delay),
+                        self.max_retries
+                    );
+                    std::thread::sleep(delay);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // Exponential backoff with a little jitter so concurrent runs don't retry in lockstep,
+    // unless the server said how long to wait.
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(d) = retry_after {
+            return d.min(MAX_DELAY);
+        }
+        let backoff = self.base_delay.saturating_mul(1 << attempt.min(16));
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let jitter = backoff.mul_f64(f64::from(nanos % 1000) / 4000.0);
+        (backoff + jitter).min(MAX_DELAY)
+    }
+}
+
+fn network(e: impl std::fmt::Display) -> ApiError {
+    ApiError {
+        kind: ErrorKind::Network,
+        status: None,
+        retry_after: None,
+        body: e.to_string(),
+    }
+}
+
+fn send_once(
+    request: reqwest::blocking::RequestBuilder,
+) -> Result<reqwest::blocking::Response, ApiError> {
+    let response = request.send().map_err(network)?;
     let status = response.status();
-    let body = response.text().context("reading response body")?;
-    if !status.is_success() {
-        anyhow::bail!("Status: {status}. Body: {body}");
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = retry_after(response.headers());
+    let body = response.text().map_err(network)?;
+    Err(ApiError {
+        kind: classify(status.as_u16(), &body),
+        status: Some(status.as_u16()),
+        retry_after,
+        body,
+    })
+}
+
+// Reassembles a streamed response into the body the non-streaming endpoint would have
+// returned.
+pub trait Stream: Default {
+    fn event(&mut self, event: SseEvent) -> Result<(), ApiError>;
+    fn finish(self) -> Result<Value, ApiError>;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: String,
+    pub data: String,
+}
+
+fn read_sse(
+    reader: impl BufRead,
+    mut on_event: impl FnMut(SseEvent) -> Result<(), ApiError>,
+) -> Result<(), ApiError> {
+    let mut event = String::new();
+    let mut data: Vec<String> = Vec::new();
+    for line in reader.lines() {
+        let line = line.map_err(network)?;
+        crate::progress::received(line.len() + 1);
+        if line.is_empty() {
+            if !data.is_empty() {
+                on_event(SseEvent {
+                    event: std::mem::take(&mut event),
+                    data: std::mem::take(&mut data).join("\n"),
+                })?;
+            }
+            event.clear();
+            continue;
+        }
+        let (field, value) = line.split_once(':').unwrap_or((&line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => event = value.to_string(),
+            "data" => data.push(value.to_string()),
+            _ => {}
+        }
     }
-    serde_json::from_str(&body)
-        .with_context(|| format!("Status: {status}. Response body was not JSON: {body}"))
+    if !data.is_empty() {
+        on_event(SseEvent {
+            event,
+            data: data.join("\n"),
+        })?;
+    }
+    Ok(())
+}
+
+// Turns an error reported inside an otherwise successful stream into an `ApiError`, so
+// that e.g. Anthropic's mid-stream `overloaded_error` is retried like a 529.
+pub fn stream_error(error: &Value) -> ApiError {
+    let kind = match error["type"].as_str().or(error["code"].as_str()) {
+        Some("overloaded_error") => ErrorKind::Overloaded,
+        Some("rate_limit_error" | "rate_limit_exceeded") => ErrorKind::RateLimited,
+        Some("api_error" | "server_error") => ErrorKind::Server,
+        Some("context_length_exceeded") => ErrorKind::ContextTooLong,
+        _ => ErrorKind::Other,
+    };
+    ApiError {
+        kind,
+        status: None,
+        retry_after: None,
+        body: error.to_string(),
+    }
+}
+
+pub fn stream_ended_early() -> ApiError {
+    network("stream ended before the response was complete")
+}
+
+// For providers whose calls don't reliably carry ids: each result goes with the call at
+// the same position in the last turn, which is the order `agent::run` answers them in.
+pub fn by_position<C>(
+    calls: &[C],
+    results: Vec<ToolResult>,
+) -> impl Iterator<Item = (Option<&C>, ToolResult)> {
+    results
+        .into_iter()
+        .enumerate()
+        .map(move |(i, r)| (calls.get(i), r))
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+    use std::time::Instant;
+
+    pub(crate) fn response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\n{headers}content-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    // Answers one connection per scripted response, then stops; returns how many requests
+    // it saw.
+    pub(crate) fn serve(responses: Vec<String>) -> (String, JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut served = 0;
+            for reply in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+                stream.write_all(reply.as_bytes()).unwrap();
+                served += 1;
+            }
+            served
+        });
+        (url, handle)
+    }
+
+    fn http(max_retries: u32) -> Http {
+        let config = Config {
+            max_retries: Some(max_retries),
+            ..Config::default()
+        };
+        Http {
+            base_delay: Duration::from_millis(1),
+            ..Http::new(&config)
+        }
+    }
+
+    fn kind(e: anyhow::Error) -> ErrorKind {
+        e.downcast::<ApiError>().unwrap().kind
+    }
+
+    #[test]
+    fn transient_failures_are_retried_until_success() {
+        let (url, server) = serve(vec![
+            response("529 Overloaded", "", r#"{"type":"overloaded_error"}"#),
+            response(
+                "429 Too Many Requests",
+                "retry-after-ms: 50\r\n",
+                "slow down",
+            ),
+            response("200 OK", "", r#"{"ok":true}"#),
+        ]);
+        let http = http(3);
+        let start = Instant::now();
+        let body = http.send_json(http.post(&url).json(&"hi")).unwrap();
+        assert_eq!(body["ok"], true);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(server.join().unwrap(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let (url, server) = serve(vec![
+            response("500 Internal Server Error", "", "boom"),
+            response("502 Bad Gateway", "", "boom"),
+        ]);
+        let http = http(1);
+        let err = http.send_json(http.post(&url)).unwrap_err();
+        assert_eq!(kind(err), ErrorKind::Server);
+        assert_eq!(server.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn permanent_failures_are_not_retried() {
+        for (status, body, expected) in [
+            ("401 Unauthorized", "invalid x-api-key", ErrorKind::Auth),
+            ("400 Bad Request", "unknown field", ErrorKind::BadRequest),
+            (
+                "400 Bad Request",
+                r#"{"error":{"code":"context_length_exceeded"}}"#,
+                ErrorKind::ContextTooLong,
+            ),
+        ] {
+            let (url, server) = serve(vec![response(status, "", body)]);
+            let http = http(3);
+            let err = http.send_json(http.post(&url)).unwrap_err();
+            assert!(err.to_string().contains(body));
+            assert_eq!(kind(err), expected);
+            assert_eq!(server.join().unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn sse_events_are_split_on_blank_lines() {
+        let stream = "event: message_start\ndata: {\"a\":1}\n\n: keepalive\n\ndata: one\ndata: two\n\ndata: [DONE]";
+        let mut events = Vec::new();
+        read_sse(stream.as_bytes(), |e| {
+            events.push(e);
+            Ok(())
+        })
+        .unwrap();
+        let event = |event: &str, data: &str| SseEvent {
+            event: event.into(),
+            data: data.into(),
+        };
+        assert_eq!(
+            events,
+            [
+                event("message_start", r#"{"a":1}"#),
+                event("", "one\ntwo"),
+                event("", "[DONE]"),
+            ]
+        );
+    }
+
+    #[test]
+    fn streams_that_fail_part_way_are_restarted() {
+        let sse = |body: &str| response("200 OK", "content-type: text/event-stream\r\n", body);
+        let (url, server) = serve(vec![
+            sse("data: 1\n\nevent: error\ndata: {\"type\":\"overloaded_error\"}\n\n"),
+            sse("data: 1\n\ndata: 2\n\n"),
+        ]);
+        #[derive(Default)]
+        struct Collect(Vec<String>);
+        impl Stream for Collect {
+            fn event(&mut self, e: SseEvent) -> Result<(), ApiError> {
+                if e.event == "error" {
+                    return Err(stream_error(&serde_json::from_str(&e.data).unwrap()));
+                }
+                self.0.push(e.data);
+                Ok(())
+            }
+            fn finish(self) -> Result<Value, ApiError> {
+                Ok(self.0.into())
+            }
+        }
+        let http = http(2);
+        let seen = http.stream_sse::<Collect>(http.post(&url)).unwrap();
+        assert_eq!(seen, serde_json::json!(["1", "2"]));
+        assert_eq!(server.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn errors_are_classified_by_status_and_body() {
+        assert_eq!(classify(429, ""), ErrorKind::RateLimited);
+        assert_eq!(classify(529, ""), ErrorKind::Overloaded);
+        assert_eq!(classify(503, ""), ErrorKind::Overloaded);
+        assert_eq!(classify(500, "Overloaded"), ErrorKind::Overloaded);
+        assert_eq!(classify(500, ""), ErrorKind::Server);
+        assert_eq!(classify(403, ""), ErrorKind::Auth);
+        assert_eq!(classify(413, ""), ErrorKind::ContextTooLong);
+        assert_eq!(
+            classify(400, "prompt is too long: 210000 tokens > 200000 maximum"),
+            ErrorKind::ContextTooLong
+        );
+        assert_eq!(classify(422, "bad schema"), ErrorKind::BadRequest);
+    }
+
+    #[test]
+    fn retry_after_is_honored_but_capped() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert("retry-after", "2".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(2)));
+        headers.insert("retry-after-ms", "250".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(250)));
+
+        let http = http(3);
+        assert_eq!(
+            http.delay(0, Some(Duration::from_secs(2))),
+            Duration::from_secs(2)
+        );
+        assert_eq!(http.delay(0, Some(Duration::from_secs(600))), MAX_DELAY);
+        assert!(http.delay(3, None) >= Duration::from_millis(8));
+    }
 
     fn tools() -> Vec<Tool> {
         crate::agent::tools()
@@ -72,8 +673,36 @@ mod tests {
     #[test]
     fn resolve_agent_errors_without_a_key() {
         let secrets = Secrets::default();
-        assert!(resolve_agent(Provider::Anthropic, "m", &secrets, &seed(), &tools()).is_err());
-        assert!(resolve_agent(Provider::Openai, "m", &secrets, &seed(), &tools()).is_err());
+        let config = Config::default();
+        let resolve = |p| resolve_agent(p, "m", &config, &secrets, &seed(), &tools());
+        assert!(resolve(Provider::Anthropic).is_err());
+        assert!(resolve(Provider::Openai).is_err());
+        assert!(resolve(Provider::Ollama).is_ok());
+        assert!(resolve(Provider::Gemini).is_err());
+    }
+
+    #[test]
+    fn custom_openai_base_url_needs_no_key() {
+        let secrets = Secrets::default();
+        let config = Config {
+            base_url: Some("http://localhost:8080/v1".into()),
+            ..Config::default()
+        };
+        let resolve = |p| resolve_agent(p, "m", &config, &secrets, &seed(), &tools());
+        assert!(resolve(Provider::Openai).is_ok());
+        assert!(resolve(Provider::Anthropic).is_err());
+    }
+
+    #[test]
+    fn replaying_a_cassette_needs_no_key() {
+        let secrets = Secrets::default();
+        let config = Config {
+            cassette: Some(Mode::Replay("c.json".into())),
+            ..Config::default()
+        };
+        let resolve = |p| resolve_agent(p, "m", &config, &secrets, &seed(), &tools());
+        assert!(resolve(Provider::Anthropic).is_ok());
+        assert!(resolve(Provider::Gemini).is_ok());
     }
 
     #[test]
@@ -81,8 +710,12 @@ mod tests {
         let secrets = Secrets {
             anthropic_api_key: Some("a".into()),
             openai_api_key: Some("o".into()),
+            gemini_api_key: Some("g".into()),
         };
-        assert!(resolve_agent(Provider::Anthropic, "m", &secrets, &seed(), &tools()).is_ok());
-        assert!(resolve_agent(Provider::Openai, "m", &secrets, &seed(), &tools()).is_ok());
+        let config = Config::default();
+        let resolve = |p| resolve_agent(p, "m", &config, &secrets, &seed(), &tools());
+        assert!(resolve(Provider::Anthropic).is_ok());
+        assert!(resolve(Provider::Openai).is_ok());
+        assert!(resolve(Provider::Gemini).is_ok());
     }
 }
@@ -0,0 +1,199 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Records each request body and the response the backend made of it, or plays them back
+// without touching the network. Headers are never written, so API keys stay out of
+// cassettes.
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+impl std::str::FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("record", path)) if !path.is_empty() => Ok(Mode::Record(path.into())),
+            Some(("replay", path)) if !path.is_empty() => Ok(Mode::Replay(path.into())),
+            _ => anyhow::bail!("expected record:<path> or replay:<path>, got {s:?}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Interaction {
+    url: String,
+    request: Value,
+    response: Value,
+}
+
+#[derive(Default)]
+struct State {
+    recorded: Vec<Interaction>,
+    remaining: Option<VecDeque<Interaction>>,
+    played: usize,
+}
+
+pub struct Cassette {
+    mode: Mode,
+    state: Mutex<State>,
+}
+
+impl Cassette {
+    pub fn new(mode: Mode) -> Self {
+        Cassette {
+            mode,
+            state: Mutex::default(),
+        }
+    }
+
+    // One cassette per mode and path for the whole process, so every agent a run builds
+    // (review follow-ups, for one) records into and replays from the same sequence.
+    pub fn shared(mode: Mode) -> Arc<Cassette> {
+        static OPEN: OnceLock<Mutex<HashMap<Mode, Arc<Cassette>>>> = OnceLock::new();
+        let mut open = OPEN
+            .get_or_init(Mutex::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        open.entry(mode.clone())
+            .or_insert_with(|| Arc::new(Cassette::new(mode)))
+            .clone()
+    }
+
+    pub fn exchange(
+        &self,
+        url: &str,
+        request: Value,
+        live: impl FnOnce() -> Result<Value>,
+    ) -> Result<Value> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match &self.mode {
+            Mode::Record(path) => {
+                let response = live()?;
+                state.recorded.push(Interaction {
+                    url: url.to_string(),
+                    request,
+                    response: response.clone(),
+                });
+                std::fs::write(path, serde_json::to_string_pretty(&state.recorded)?)
+                    .with_context(|| format!("writing cassette {}", path.display()))?;
+                Ok(response)
+            }
+            Mode::Replay(path) => {
+                if state.remaining.is_none() {
+                    let contents = std::fs::read_to_string(path)
+                        .with_context(|| format!("reading cassette {}", path.display()))?;
+                    let interactions = serde_json::from_str(&contents)
+                        .with_context(|| format!("parsing cassette {}", path.display()))?;
+                    state.remaining = Some(interactions);
+                }
+                state.played += 1;
+                let n = state.played;
+                let Some(next) = state.remaining.as_mut().and_then(VecDeque::pop_front) else {
+                    anyhow::bail!("request {n} is past the end of cassette {}", path.display());
+                };
+                if next.url != url || next.request != request {
+                    let pretty = |url: &str, v: &Value| {
+                        format!(
+                            "{url}\n{}\n",
+                            serde_json::to_string_pretty(v).unwrap_or_default()
+                        )
+                    };
+                    anyhow::bail!(
+                        "request {n} differs from the one recorded in {}:\n{}",
+                        path.display(),
+                        crate::diff::unified(
+                            &pretty(&next.url, &next.request),
+                            &pretty(url, &request),
+                            "request"
+                        )
+                    );
+                }
+                Ok(next.response)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn mode_is_parsed_from_the_env_syntax() {
+        assert_eq!(
+            "record:/tmp/c.json".parse::<Mode>().unwrap(),
+            Mode::Record("/tmp/c.json".into())
+        );
+        assert_eq!(
+            "replay:c.json".parse::<Mode>().unwrap(),
+            Mode::Replay("c.json".into())
+        );
+        assert!("replay:".parse::<Mode>().is_err());
+        assert!("play:c.json".parse::<Mode>().is_err());
+    }
+
+    #[test]
+    fn recorded_exchanges_replay_in_order_and_must_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.json");
+
+        let recorder = Cassette::new(Mode::Record(path.clone()));
+        for i in 0..2 {
+            let got = recorder
+                .exchange("http://x/", json!({ "turn": i }), || {
+                    Ok(json!({ "reply": i }))
+                })
+                .unwrap();
+            assert_eq!(got, json!({ "reply": i }));
+        }
+
+        let player = Cassette::new(Mode::Replay(path.clone()));
+        let offline = || -> Result<Value> { panic!("replay must not hit the network") };
+        assert_eq!(
+            player
+                .exchange("http://x/", json!({ "turn": 0 }), offline)
+                .unwrap(),
+            json!({ "reply": 0 })
+        );
+        let err = player
+            .exchange("http://x/", json!({ "turn": 5 }), offline)
+            .unwrap_err();
+        assert!(err.to_string().contains("request 2 differs"));
+        assert!(err.to_string().contains("+  \"turn\": 5"));
+        let err = player
+            .exchange("http://x/", json!({ "turn": 1 }), offline)
+            .unwrap_err();
+        assert!(err.to_string().contains("past the end"));
+    }
+
+    #[test]
+    fn shared_cassettes_span_every_agent_in_the_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.json");
+
+        for i in 0..2 {
+            Cassette::shared(Mode::Record(path.clone()))
+                .exchange("http://x/", json!({ "agent": i }), || {
+                    Ok(json!({ "reply": i }))
+                })
+                .unwrap();
+        }
+        let offline = || -> Result<Value> { panic!("replay must not hit the network") };
+        for i in 0..2 {
+            let got = Cassette::shared(Mode::Replay(path.clone()))
+                .exchange("http://x/", json!({ "agent": i }), offline)
+                .unwrap();
+            assert_eq!(got, json!({ "reply": i }));
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use xdg::BaseDirectories;
 
@@ -13,6 +14,8 @@ pub struct Secrets {
     pub openai_api_key: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub anthropic_api_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gemini_api_key: Option<String>,
 }
 
 impl Secrets {
@@ -27,6 +30,9 @@ impl Secrets {
         if let Ok(key) = std::env::var("ANTHROPIC_API_KEY") {
             secrets.anthropic_api_key = Some(key);
         }
+        if let Ok(key) = std::env::var("GEMINI_API_KEY") {
+            secrets.gemini_api_key = Some(key);
+        }
         Ok(secrets)
     }
 
@@ -58,6 +64,16 @@ impl Secrets {
 pub enum Provider {
     Anthropic,
     Openai,
+    Ollama,
+    Gemini,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OpenaiApi {
+    #[default]
+    Chat,
+    Responses,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -66,6 +82,36 @@ pub struct Config {
     pub provider: Option<Provider>,
     #[serde(default)]
     pub model: Option<String>,
+    #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(default)]
+    pub openai_api: Option<OpenaiApi>,
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    #[serde(default)]
+    pub prices: HashMap<String, Price>,
+    /// Anthropic extended thinking budget in tokens; 0 or unset turns thinking off.
+    #[serde(default)]
+    pub thinking_budget: Option<u32>,
+    /// Passed through to OpenAI as `reasoning_effort`, e.g. "minimal", "low", "high".
+    #[serde(default)]
+    pub reasoning_effort: Option<String>,
+    /// How similar (0 to 1) a region must be for `edit` to accept it as a misquoted `old`.
+    #[serde(default)]
+    pub fuzzy_threshold: Option<f64>,
+    #[serde(skip)]
+    pub cassette: Option<crate::cassette::Mode>,
+}
+
+/// USD per million tokens. Cache reads and writes are billed at the input price unless set.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+    #[serde(default)]
+    pub cache_read: Option<f64>,
+    #[serde(default)]
+    pub cache_write: Option<f64>,
 }
 
 impl Config {
@@ -82,9 +128,63 @@ impl Config {
         if let Ok(from_env) = std::env::var("REFAC_MODEL") {
             ret.model = Some(from_env);
         }
+        if let Ok(from_env) = std::env::var("REFAC_BASE_URL") {
+            ret.base_url = Some(from_env);
+        }
+        if let Ok(from_env) = std::env::var("REFAC_OPENAI_API") {
+            let api = clap::ValueEnum::from_str(&from_env, true)
+                .map_err(|e| anyhow::anyhow!("invalid REFAC_OPENAI_API: {e}"))?;
+            ret.openai_api = Some(api);
+        }
+        if let Ok(from_env) = std::env::var("REFAC_MAX_RETRIES") {
+            let n = from_env
+                .parse()
+                .map_err(|e| anyhow::anyhow!("invalid REFAC_MAX_RETRIES: {e}"))?;
+            ret.max_retries = Some(n);
+        }
+        if let Ok(from_env) = std::env::var("REFAC_THINKING_BUDGET") {
+            let n = from_env
+                .parse()
+                .map_err(|e| anyhow::anyhow!("invalid REFAC_THINKING_BUDGET: {e}"))?;
+            ret.thinking_budget = Some(n);
+        }
+        if let Ok(from_env) = std::env::var("REFAC_REASONING_EFFORT") {
+            ret.reasoning_effort = Some(from_env);
+        }
+        if let Ok(from_env) = std::env::var("REFAC_FUZZY_THRESHOLD") {
+            let t = from_env
+                .parse()
+                .map_err(|e| anyhow::anyhow!("invalid REFAC_FUZZY_THRESHOLD: {e}"))?;
+            ret.fuzzy_threshold = Some(t);
+        }
+        if let Ok(from_env) = std::env::var("REFAC_CASSETTE") {
+            let mode = from_env
+                .parse()
+                .map_err(|e| anyhow::anyhow!("invalid REFAC_CASSETTE: {e}"))?;
+            ret.cassette = Some(mode);
+        }
+        ret.validate()?;
         Ok(ret)
     }
 
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(b) = self.thinking_budget.filter(|&b| b > 0) {
+            use crate::anthropic::{MAX_TOKENS, MIN_THINKING_BUDGET};
+            anyhow::ensure!(
+                (MIN_THINKING_BUDGET..MAX_TOKENS).contains(&b),
+                "thinking_budget must be 0 (off) or from {MIN_THINKING_BUDGET} up to {}, got {b}",
+                MAX_TOKENS - 1
+            );
+        }
+        if let Some(t) = self.fuzzy_threshold {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&t),
+                "fuzzy_threshold must be between 0 and 1, got {t}"
+            );
+        }
+        Ok(())
+    }
+
     pub fn provider(&self, secrets: &Secrets) -> Provider {
         if let Some(p) = self.provider {
             return p;
@@ -92,18 +192,30 @@ impl Config {
         match (
             secrets.anthropic_api_key.is_some(),
             secrets.openai_api_key.is_some(),
+            secrets.gemini_api_key.is_some(),
         ) {
-            (false, true) => Provider::Openai,
+            (false, true, _) => Provider::Openai,
+            (false, false, true) => Provider::Gemini,
             _ => Provider::Anthropic,
         }
     }
 
+    pub fn price(&self, provider: Provider, model: &str) -> Option<Price> {
+        match self.prices.get(model) {
+            Some(price) => Some(*price),
+            None if provider == Provider::Ollama => Some(Price::default()),
+            None => None,
+        }
+    }
+
     pub fn model(&self, provider: Provider) -> String {
         match &self.model {
             Some(m) => m.clone(),
             None => match provider {
                 Provider::Anthropic => "claude-opus-4-8".to_string(),
                 Provider::Openai => "gpt-5.5".to_string(),
+                Provider::Ollama => "qwen3".to_string(),
+                Provider::Gemini => "gemini-2.5-pro".to_string(),
             },
         }
     }
@@ -117,6 +229,7 @@ mod tests {
         Secrets {
             anthropic_api_key: anthropic.then(|| "a".to_string()),
             openai_api_key: openai.then(|| "o".to_string()),
+            gemini_api_key: None,
         }
     }
 
@@ -129,6 +242,21 @@ mod tests {
         assert_eq!(cfg.provider(&secrets(false, false)), Provider::Anthropic);
     }
 
+    #[test]
+    fn gemini_inferred_only_when_it_is_the_sole_key() {
+        let cfg = Config::default();
+        let only_gemini = Secrets {
+            gemini_api_key: Some("g".into()),
+            ..secrets(false, false)
+        };
+        assert_eq!(cfg.provider(&only_gemini), Provider::Gemini);
+        let with_openai = Secrets {
+            gemini_api_key: Some("g".into()),
+            ..secrets(false, true)
+        };
+        assert_eq!(cfg.provider(&with_openai), Provider::Openai);
+    }
+
     #[test]
     fn explicit_provider_overrides_inference() {
         let cfg = Config {
@@ -137,4 +265,38 @@ mod tests {
         };
         assert_eq!(cfg.provider(&secrets(true, false)), Provider::Openai);
     }
+
+    #[test]
+    fn thinking_budget_is_checked_against_the_api_limits() {
+        let with_budget = |thinking_budget| Config {
+            thinking_budget,
+            ..Config::default()
+        };
+        assert!(with_budget(None).validate().is_ok());
+        assert!(with_budget(Some(0)).validate().is_ok());
+        assert!(with_budget(Some(1024)).validate().is_ok());
+        assert!(with_budget(Some(1023)).validate().is_err());
+        assert!(with_budget(Some(crate::anthropic::MAX_TOKENS))
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn prices_come_from_the_config_and_ollama_is_free() {
+        let cfg: Config = toml::from_str(
+            r#"
+            [prices."claude-opus-4-8"]
+            input = 5.0
+            output = 25.0
+            cache_read = 0.5
+            "#,
+        )
+        .unwrap();
+        let price = cfg.price(Provider::Anthropic, "claude-opus-4-8").unwrap();
+        assert_eq!(price.output, 25.0);
+        assert_eq!(price.cache_read, Some(0.5));
+        assert_eq!(price.cache_write, None);
+        assert_eq!(cfg.price(Provider::Openai, "gpt-5.5"), None);
+        assert_eq!(cfg.price(Provider::Ollama, "qwen3"), Some(Price::default()));
+    }
 }
@@ -0,0 +1,34 @@
+use similar::TextDiff;
+
+pub fn unified(original: &str, output: &str, name: &str) -> String {
+    TextDiff::from_lines(original, output)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{name}"), &format!("b/{name}"))
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unified_diff_has_git_style_headers() {
+        let got = unified("a\nb\nc\n", "a\nB\nc\n", "src/x.rs");
+        assert_eq!(
+            got,
+            "--- a/src/x.rs\n+++ b/src/x.rs\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n"
+        );
+    }
+
+    #[test]
+    fn no_change_is_empty() {
+        assert_eq!(unified("same\n", "same\n", "x"), "");
+    }
+
+    #[test]
+    fn missing_trailing_newline_is_marked() {
+        let got = unified("a\n", "a\nb", "x");
+        assert!(got.contains("\\ No newline at end of file"));
+    }
+}
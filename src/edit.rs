@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -12,21 +15,84 @@ pub struct Edit {
     pub replace_all: bool,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct LineEdit {
+    #[schemars(description = "first line to replace, counting from 1")]
+    pub start: usize,
+    #[schemars(
+        description = "last line to replace, inclusive; use start - 1 to insert before start"
+    )]
+    pub end: usize,
+    #[schemars(description = "replacement lines; empty to delete")]
+    pub new: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct RegexEdit {
+    #[schemars(
+        description = "regular expression (Rust regex syntax); (?m) makes ^ and $ match at lines"
+    )]
+    pub pattern: String,
+    #[schemars(
+        description = "replacement; $1 or ${name} insert capture groups, $$ is a literal $"
+    )]
+    pub replacement: String,
+    #[schemars(description = "replace at most this many matches, first to last; default all")]
+    #[serde(default)]
+    pub max_count: Option<usize>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EditError {
-    NotFound { old: String },
-    Ambiguous { old: String, count: usize },
-    NoChange { old: String },
+    NotFound {
+        old: String,
+        // The most similar region of the text, with its first line number.
+        closest: Option<(usize, String)>,
+    },
+    Ambiguous {
+        old: String,
+        count: usize,
+    },
+    NoChange {
+        old: String,
+    },
     EmptyOld,
+    LineRange {
+        start: usize,
+        end: usize,
+        lines: usize,
+    },
+    BadPattern {
+        pattern: String,
+        error: String,
+    },
+    NoMatch {
+        pattern: String,
+    },
+    Conflict {
+        start_line: usize,
+        end_line: usize,
+        written: String,
+    },
 }
 
 impl std::fmt::Display for EditError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            EditError::NotFound { old } => write!(
-                f,
-                "could not find this text to edit (copy it verbatim from the selection): {old:?}"
-            ),
+            EditError::NotFound { old, closest } => {
+                write!(
+                    f,
+                    "could not find this text to edit (copy it verbatim from the selection): {old:?}"
+                )?;
+                if let Some((line, region)) = closest {
+                    write!(
+                        f,
+                        "\nthe closest text starts at line {line}; it differs from old like this:\n{}",
+                        hint_diff(old, region)
+                    )?;
+                }
+                Ok(())
+            }
             EditError::Ambiguous { old, count } => write!(
                 f,
                 "found {count} matches for {old:?}; add surrounding context to make it unique, or set replace_all"
@@ -38,13 +104,133 @@ impl std::fmt::Display for EditError {
                 f,
                 "old is empty; to insert, anchor on existing text and include it in both old and new"
             ),
+            EditError::LineRange { start, end, lines } => write!(
+                f,
+                "lines {start}-{end} are not a valid range; the text has {lines} lines (view with line_numbers to check)"
+            ),
+            EditError::BadPattern { pattern, error } => {
+                write!(f, "invalid regex {pattern:?}: {error}")
+            }
+            EditError::NoMatch { pattern } => {
+                write!(f, "regex {pattern:?} matches nothing in the current text")
+            }
+            EditError::Conflict {
+                start_line,
+                end_line,
+                written,
+            } => write!(
+                f,
+                "this overlaps lines {start_line}-{end_line}, which an earlier edit in this turn just wrote as {written:?}; make one edit per region, or view the text and retry next turn"
+            ),
         }
     }
 }
 
 impl std::error::Error for EditError {}
 
-pub fn apply(src: &str, edit: &Edit) -> Result<String, EditError> {
+// Similarity (0 to 1) a region needs before the fuzzy replacer will take it for `old`.
+pub const DEFAULT_FUZZY_THRESHOLD: f64 = 0.9;
+
+// One replacement, as a byte range of the text it was resolved against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Splice {
+    pub range: Range<usize>,
+    pub new: String,
+}
+
+// Applies splices that are sorted and don't overlap.
+pub fn splice(src: &str, splices: &[Splice]) -> String {
+    let mut out = String::with_capacity(src.len());
+    let mut at = 0;
+    for s in splices {
+        out.push_str(&src[at..s.range.start]);
+        out.push_str(&s.new);
+        at = s.range.end;
+    }
+    out.push_str(&src[at..]);
+    out
+}
+
+// Where the edits of one turn have written, in the coordinates of the current text, and
+// what each of those spans replaced in the text as the turn started.
+#[derive(Debug, Default)]
+pub struct Written {
+    spans: Vec<Range<usize>>,
+    replaced: Vec<Range<usize>>,
+}
+
+impl Written {
+    // Refuses splices that would touch text an earlier edit wrote; the model chose them
+    // without seeing that text.
+    pub fn check(&self, src: &str, splices: &[Splice]) -> Result<(), EditError> {
+        self.refuse(src, &self.spans, splices)
+    }
+
+    // Like `check`, for splices resolved against the text as the turn started: refuses
+    // those that reach into text an earlier edit has since replaced.
+    pub fn check_started(&self, src: &str, splices: &[Splice]) -> Result<(), EditError> {
+        self.refuse(src, &self.replaced, splices)
+    }
+
+    fn refuse(
+        &self,
+        src: &str,
+        ranges: &[Range<usize>],
+        splices: &[Splice],
+    ) -> Result<(), EditError> {
+        for (w, r) in self.spans.iter().zip(ranges) {
+            if splices
+                .iter()
+                .any(|s| s.range.start < r.end && r.start < s.range.end)
+            {
+                let line = |i: usize| src[..i].matches('\n').count() + 1;
+                return Err(EditError::Conflict {
+                    start_line: line(w.start),
+                    end_line: line(w.end.saturating_sub(1).max(w.start)),
+                    written: src[w.clone()].to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Call after applying `splices`, which passed `check`.
+    pub fn record(&mut self, splices: &[Splice]) {
+        let started = |i: usize| -> usize {
+            let before = || {
+                self.spans
+                    .iter()
+                    .zip(&self.replaced)
+                    .filter(|(w, _)| w.end <= i)
+            };
+            let grown: usize = before().map(|(w, _)| w.len()).sum();
+            let shrunk: usize = before().map(|(_, r)| r.len()).sum();
+            i + shrunk - grown
+        };
+        let replaced: Vec<Range<usize>> = splices
+            .iter()
+            .map(|s| started(s.range.start)..started(s.range.start) + s.range.len())
+            .collect();
+        let shift = |i: usize| -> usize {
+            let before = splices.iter().filter(|s| s.range.end <= i);
+            let grown: usize = before.clone().map(|s| s.new.len()).sum();
+            let shrunk: usize = before.map(|s| s.range.len()).sum();
+            i + grown - shrunk
+        };
+        for w in &mut self.spans {
+            *w = shift(w.start)..shift(w.start) + w.len();
+        }
+        let mut delta = 0isize;
+        for s in splices {
+            let start = (s.range.start as isize + delta) as usize;
+            self.spans.push(start..start + s.new.len());
+            delta += s.new.len() as isize - s.range.len() as isize;
+        }
+        self.replaced.extend(replaced);
+    }
+}
+
+pub fn resolve(src: &str, edit: &Edit, fuzzy_threshold: f64) -> Result<Vec<Splice>, EditError> {
     if edit.old.is_empty() {
         return Err(EditError::EmptyOld);
     }
@@ -57,21 +243,22 @@ pub fn apply(src: &str, edit: &Edit) -> Result<String, EditError> {
     let mut ambiguous: Option<usize> = None;
 
     for replacer in CHAIN {
-        for cand in replacer(src, &edit.old) {
+        for cand in replacer(src, &edit.old, fuzzy_threshold) {
             if cand.is_empty() {
                 continue;
             }
             let count = src.matches(cand.as_str()).count();
             match (count, edit.replace_all) {
                 (0, _) => continue,
-                (_, true) => return Ok(src.replace(cand.as_str(), &edit.new)),
-                (1, false) => {
-                    let i = src.find(cand.as_str()).expect("count == 1");
-                    let mut out = String::with_capacity(src.len() - cand.len() + edit.new.len());
-                    out.push_str(&src[..i]);
-                    out.push_str(&edit.new);
-                    out.push_str(&src[i + cand.len()..]);
-                    return Ok(out);
+                (1, _) | (_, true) => {
+                    let new = reindented(src, &cand, edit);
+                    return Ok(src
+                        .match_indices(cand.as_str())
+                        .map(|(i, _)| Splice {
+                            range: i..i + cand.len(),
+                            new: new.clone(),
+                        })
+                        .collect());
                 }
                 (n, false) => ambiguous = Some(ambiguous.map_or(n, |m| m.max(n))),
             }
@@ -85,11 +272,250 @@ pub fn apply(src: &str, edit: &Edit) -> Result<String, EditError> {
         },
         None => EditError::NotFound {
             old: edit.old.clone(),
+            closest: closest(src, &edit.old),
         },
     })
 }
 
-type Replacer = fn(src: &str, old: &str) -> Vec<String>;
+// Below this similarity a "closest" region is more likely to mislead than help.
+const HINT_RATIO: f32 = 0.5;
+
+// Windows worth a character diff once a cheap score has ranked them all.
+const HINT_SHORTLIST: usize = 3;
+
+fn closest(src: &str, old: &str) -> Option<(usize, String)> {
+    let src_lines = lines_with_offsets(src);
+    let old_lines = lines_with_offsets(old);
+    let n = old_lines.len().min(src_lines.len());
+    if n == 0 {
+        return None;
+    }
+    // Rank every window line by line on shared characters, which is linear in its length;
+    // a full character diff on each window is quadratic and takes minutes on large files.
+    let src_hist: Vec<_> = src_lines.iter().map(|(_, l)| histogram(l)).collect();
+    let old_hist: Vec<_> = old_lines.iter().map(|(_, l)| histogram(l)).collect();
+    let mut ranked: Vec<(f32, usize)> = (0..=src_lines.len() - n)
+        .map(|i| {
+            let (shared, longest) = (0..n).fold((0, 0), |(s, l), j| {
+                let (a, b) = (&src_hist[i + j], &old_hist[j]);
+                let shared: u32 = a.1.iter().zip(&b.1).map(|(x, y)| x.min(y)).sum();
+                (s + shared, l + a.0.max(b.0))
+            });
+            (shared as f32 / longest.max(1) as f32, i)
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_millis(200);
+    let (ratio, i) = ranked
+        .into_iter()
+        .take(HINT_SHORTLIST)
+        .map(|(_, i)| {
+            let window = span(src, &src_lines, i, i + n - 1);
+            let ratio = similar::TextDiff::configure()
+                .deadline(deadline)
+                .diff_chars(window.as_str(), old)
+                .ratio();
+            (ratio, i)
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)))?;
+    (ratio >= HINT_RATIO).then(|| (i + 1, span(src, &src_lines, i, i + n - 1)))
+}
+
+// A trimmed line's length in characters, and how often each character occurs in it, with
+// characters folded into 256 buckets.
+fn histogram(line: &str) -> (u32, [u32; 256]) {
+    let mut counts = [0; 256];
+    let mut len = 0;
+    for c in line.trim().chars() {
+        counts[c as usize % 256] += 1;
+        len += 1;
+    }
+    (len, counts)
+}
+
+fn hint_diff(old: &str, region: &str) -> String {
+    let (old, region) = (format!("{old}\n"), format!("{region}\n"));
+    similar::TextDiff::from_lines(old.as_str(), region.as_str())
+        .unified_diff()
+        .context_radius(2)
+        .header("old", "text")
+        .to_string()
+}
+
+// When a line-based replacer matched `old` at another indentation, shifts `new` by the same
+// amount, in the buffer's tabs or spaces. `new` is left alone if it isn't at `old`'s
+// indentation, since the model has then usually indented it for its real position already.
+fn reindented(src: &str, cand: &str, edit: &Edit) -> String {
+    let i = src.find(cand).expect("candidates come from src");
+    let at_line_start = i == 0 || src[..i].ends_with('\n');
+    let old_base = base_indent(&edit.old);
+    if cand == edit.old || !at_line_start || base_indent(&edit.new) != old_base {
+        return edit.new.clone();
+    }
+    let region_base = base_indent(cand);
+    let target = indent_style(src);
+    let lines: Vec<(&str, &str)> = edit
+        .new
+        .split_inclusive('\n')
+        .map(|line| match line.trim().is_empty() {
+            true => ("", line),
+            false => {
+                let rest = line.strip_prefix(old_base).unwrap_or(line.trim_start());
+                let text = rest.trim_start();
+                (&rest[..rest.len() - text.len()], text)
+            }
+        })
+        .collect();
+    let model_width = indent_unit(
+        lines
+            .iter()
+            .filter(|(_, text)| !text.trim().is_empty())
+            .map(|(ws, _)| ws.chars().take_while(|&c| c == ' ').count()),
+    );
+    let mut out = String::with_capacity(edit.new.len());
+    for (relative, text) in lines {
+        if !text.trim().is_empty() {
+            out.push_str(region_base);
+            out.push_str(&convert_indent(relative, target, model_width));
+        }
+        out.push_str(text);
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Indent {
+    Tabs,
+    Spaces(usize),
+}
+
+// The shortest indentation of any non-blank line.
+fn base_indent(s: &str) -> &str {
+    s.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| &l[..l.len() - l.trim_start().len()])
+        .min_by_key(|ws| ws.len())
+        .unwrap_or("")
+}
+
+fn indent_style(s: &str) -> Indent {
+    let tabs = s.lines().filter(|l| l.starts_with('\t')).count();
+    let spaced = s.lines().filter(|l| l.starts_with(' ')).count();
+    if tabs > spaced {
+        return Indent::Tabs;
+    }
+    Indent::Spaces(indent_unit(
+        s.lines()
+            .filter(|l| !l.trim().is_empty() && !l.starts_with('\t'))
+            .map(|l| l.len() - l.trim_start_matches(' ').len()),
+    ))
+}
+
+// The usual step between one line's leading spaces and a deeper next line's, so that a
+// selection nested several levels in still reads as 4-wide rather than 8 or 12.
+// Falls back to the shallowest indentation, then to 4, when no line is deeper than the last.
+fn indent_unit(widths: impl IntoIterator<Item = usize>) -> usize {
+    let mut steps: HashMap<usize, usize> = HashMap::new();
+    let mut shallowest = None;
+    let mut prev = None;
+    for width in widths {
+        if let Some(prev) = prev.filter(|&p| width > p) {
+            *steps.entry(width - prev).or_default() += 1;
+        }
+        if width > 0 {
+            shallowest = Some(shallowest.map_or(width, |s: usize| s.min(width)));
+        }
+        prev = Some(width);
+    }
+    steps
+        .into_iter()
+        .max_by_key(|&(step, count)| (count, std::cmp::Reverse(step)))
+        .map(|(step, _)| step)
+        .or(shallowest)
+        .unwrap_or(4)
+}
+
+// Rewrites relative indentation written `model_width` spaces to a level into `target`.
+fn convert_indent(ws: &str, target: Indent, model_width: usize) -> String {
+    let tabs = ws.chars().filter(|&c| c == '\t').count();
+    let spaces = ws.chars().filter(|&c| c == ' ').count();
+    match target {
+        Indent::Tabs => format!(
+            "{}{}",
+            "\t".repeat(tabs + spaces / model_width),
+            " ".repeat(spaces % model_width)
+        ),
+        Indent::Spaces(width) => format!("{}{}", " ".repeat(tabs * width), " ".repeat(spaces)),
+    }
+}
+
+pub fn resolve_lines(src: &str, edit: &LineEdit) -> Result<Splice, EditError> {
+    let mut offsets: Vec<usize> = lines_with_offsets(src).iter().map(|l| l.0).collect();
+    let lines = offsets.len();
+    offsets.push(src.len());
+    let LineEdit { start, end, .. } = *edit;
+    if start == 0 || end + 1 < start || end > lines {
+        return Err(EditError::LineRange { start, end, lines });
+    }
+    let (lo, hi) = (offsets[start - 1], offsets[end]);
+    let old = &src[lo..hi];
+
+    let mut new = edit.new.clone();
+    let followed = old.ends_with('\n') || (old.is_empty() && lo < src.len());
+    if followed && !new.is_empty() && !new.ends_with('\n') {
+        new.push('\n');
+    }
+    if lo == src.len() && !src.is_empty() && !src.ends_with('\n') && !new.is_empty() {
+        new.insert(0, '\n');
+    }
+    if new == old {
+        return Err(EditError::NoChange {
+            old: old.to_string(),
+        });
+    }
+    Ok(Splice { range: lo..hi, new })
+}
+
+pub fn resolve_regex(src: &str, edit: &RegexEdit) -> Result<Vec<Splice>, EditError> {
+    let re = regex::Regex::new(&edit.pattern).map_err(|e| EditError::BadPattern {
+        pattern: edit.pattern.clone(),
+        error: e.to_string(),
+    })?;
+    let splices: Vec<Splice> = re
+        .captures_iter(src)
+        .take(edit.max_count.unwrap_or(usize::MAX))
+        .map(|caps| {
+            let mut new = String::new();
+            caps.expand(&edit.replacement, &mut new);
+            let range = caps.get(0).expect("group 0 always matches").range();
+            Splice { range, new }
+        })
+        .collect();
+    if splices.is_empty() {
+        return Err(EditError::NoMatch {
+            pattern: edit.pattern.clone(),
+        });
+    }
+    if splices.iter().all(|s| src[s.range.clone()] == s.new) {
+        return Err(EditError::NoChange {
+            old: edit.pattern.clone(),
+        });
+    }
+    Ok(splices)
+}
+
+pub fn numbered(src: &str) -> String {
+    let lines = lines_with_offsets(src);
+    let width = lines.len().to_string().len();
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, (_, line))| format!("{:>width$}\t{line}\n", i + 1))
+        .collect()
+}
+
+type Replacer = fn(src: &str, old: &str, fuzzy_threshold: f64) -> Vec<String>;
 
 const CHAIN: &[Replacer] = &[
     simple,
@@ -97,18 +523,23 @@ const CHAIN: &[Replacer] = &[
     block_anchor,
     whitespace_normalized,
     indentation_flexible,
+    fuzzy,
 ];
 
-fn simple(_src: &str, old: &str) -> Vec<String> {
+fn simple(_src: &str, old: &str, _: f64) -> Vec<String> {
     vec![old.to_string()]
 }
 
 fn lines_with_offsets(s: &str) -> Vec<(usize, &str)> {
     let mut out = Vec::new();
     let mut start = 0;
-    for line in s.split_inclusive('\n') {
-        out.push((start, line.strip_suffix('\n').unwrap_or(line)));
-        start += line.len();
+    for raw in s.split_inclusive('\n') {
+        let line = match raw.strip_suffix('\n') {
+            Some(l) => l.strip_suffix('\r').unwrap_or(l),
+            None => raw,
+        };
+        out.push((start, line));
+        start += raw.len();
     }
     out
 }
@@ -119,7 +550,7 @@ fn span(src: &str, lines: &[(usize, &str)], i: usize, k: usize) -> String {
     src[start..end].to_string()
 }
 
-fn line_trimmed(src: &str, old: &str) -> Vec<String> {
+fn line_trimmed(src: &str, old: &str, _: f64) -> Vec<String> {
     let src_lines = lines_with_offsets(src);
     let old_lines: Vec<&str> = lines_with_offsets(old).iter().map(|(_, l)| *l).collect();
     let n = old_lines.len();
@@ -135,7 +566,7 @@ fn line_trimmed(src: &str, old: &str) -> Vec<String> {
     out
 }
 
-fn block_anchor(src: &str, old: &str) -> Vec<String> {
+fn block_anchor(src: &str, old: &str, _: f64) -> Vec<String> {
     let src_lines = lines_with_offsets(src);
     let old_lines: Vec<&str> = lines_with_offsets(old).iter().map(|(_, l)| *l).collect();
     let n = old_lines.len();
@@ -168,7 +599,7 @@ fn block_anchor(src: &str, old: &str) -> Vec<String> {
     out
 }
 
-fn whitespace_normalized(src: &str, old: &str) -> Vec<String> {
+fn whitespace_normalized(src: &str, old: &str, _: f64) -> Vec<String> {
     let tokens: Vec<&str> = old.split_whitespace().collect();
     if tokens.is_empty() {
         return vec![];
@@ -199,7 +630,7 @@ fn whitespace_normalized(src: &str, old: &str) -> Vec<String> {
     out
 }
 
-fn indentation_flexible(src: &str, old: &str) -> Vec<String> {
+fn indentation_flexible(src: &str, old: &str, _: f64) -> Vec<String> {
     let src_lines = lines_with_offsets(src);
     let old_lines: Vec<&str> = lines_with_offsets(old).iter().map(|(_, l)| *l).collect();
     let n = old_lines.len();
@@ -217,6 +648,116 @@ fn indentation_flexible(src: &str, old: &str) -> Vec<String> {
     out
 }
 
+// A single line shorter than this is left to the exact replacers: one changed character in
+// `if x.len() > 0 {` is still 90% similar to its neighbour, and means the opposite.
+const FUZZY_MIN_LINE: usize = 24;
+
+// Catches a misquoted token in an otherwise verbatim block. Compares trimmed lines, so
+// indentation drift doesn't count against the similarity, and gives up unless exactly one
+// region is close enough.
+fn fuzzy(src: &str, old: &str, threshold: f64) -> Vec<String> {
+    let src_lines = lines_with_offsets(src);
+    let old_lines: Vec<&str> = lines_with_offsets(old).iter().map(|(_, l)| *l).collect();
+    let n = old_lines.len();
+    if n == 0 || n > src_lines.len() || threshold >= 1.0 {
+        return vec![];
+    }
+    let want: Vec<char> = trimmed(&old_lines).chars().collect();
+    if n == 1 && want.len() < FUZZY_MIN_LINE {
+        return vec![];
+    }
+    let want_hist = old_lines
+        .iter()
+        .fold([0; 256], |h, l| add(h, &histogram(l).1));
+    let line_hists: Vec<_> = src_lines.iter().map(|(_, l)| histogram(l)).collect();
+    let mut hist = line_hists[..n].iter().fold([0; 256], |h, l| add(h, &l.1));
+    let mut out = Vec::new();
+    for i in 0..=src_lines.len() - n {
+        if i > 0 {
+            hist = add(hist, &line_hists[i + n - 1].1);
+            for (h, gone) in hist.iter_mut().zip(&line_hists[i - 1].1) {
+                *h -= gone;
+            }
+        }
+        let got_len = line_hists[i..i + n]
+            .iter()
+            .map(|l| l.0 as usize)
+            .sum::<usize>()
+            + n
+            - 1;
+        let longest = got_len.max(want.len());
+        if longest == 0 {
+            continue;
+        }
+        let budget = ((1.0 - threshold.max(0.0)) * longest as f64).floor() as usize;
+        // Each edit changes at most two character counts by one, so this bounds the
+        // distance from below without the quadratic comparison.
+        let differing: u32 = hist
+            .iter()
+            .zip(&want_hist)
+            .map(|(a, b)| a.abs_diff(*b))
+            .sum();
+        if differing as usize > 2 * budget {
+            continue;
+        }
+        let window: Vec<&str> = (i..i + n).map(|j| src_lines[j].1).collect();
+        let got: Vec<char> = trimmed(&window).chars().collect();
+        if levenshtein_within(&got, &want, budget).is_some() {
+            out.push(span(src, &src_lines, i, i + n - 1));
+            if out.len() > 1 {
+                return vec![];
+            }
+        }
+    }
+    out
+}
+
+fn add(mut a: [u32; 256], b: &[u32; 256]) -> [u32; 256] {
+    for (x, y) in a.iter_mut().zip(b) {
+        *x += y;
+    }
+    a
+}
+
+fn trimmed(lines: &[&str]) -> String {
+    lines
+        .iter()
+        .map(|l| l.trim())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Edit distance between `a` and `b`, or None if it's more than `max`. Only cells within `max`
+// of the diagonal can stay under the budget, so only those are computed.
+fn levenshtein_within(a: &[char], b: &[char], max: usize) -> Option<usize> {
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+    let over = max + 1;
+    let mut prev: Vec<usize> = (0..=b.len()).map(|j| j.min(over)).collect();
+    let mut cur = vec![over; b.len() + 1];
+    for i in 1..=a.len() {
+        let lo = i.saturating_sub(max).max(1);
+        let hi = (i + max).min(b.len());
+        cur.fill(over);
+        if i <= max {
+            cur[0] = i;
+        }
+        for j in lo..=hi {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            cur[j] = (prev[j - 1] + cost)
+                .min(prev[j] + 1)
+                .min(cur[j - 1] + 1)
+                .min(over);
+        }
+        if cur[lo - 1..=hi].iter().all(|&d| d > max) {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    Some(prev[b.len()]).filter(|&d| d <= max)
+}
+
 fn dedent(lines: &[&str]) -> Vec<String> {
     let indent = lines
         .iter()
@@ -234,6 +775,25 @@ fn dedent(lines: &[&str]) -> Vec<String> {
 mod tests {
     use super::*;
 
+    fn apply(src: &str, edit: &Edit, fuzzy_threshold: f64) -> Result<String, EditError> {
+        resolve(src, edit, fuzzy_threshold).map(|splices| splice(src, &splices))
+    }
+
+    // Returns the edited text and the text that was replaced.
+    fn apply_lines(src: &str, edit: &LineEdit) -> Result<(String, String), EditError> {
+        let s = resolve_lines(src, edit)?;
+        Ok((
+            splice(src, std::slice::from_ref(&s)),
+            src[s.range].to_string(),
+        ))
+    }
+
+    // Returns the edited text and how many matches were replaced.
+    fn apply_regex(src: &str, edit: &RegexEdit) -> Result<(String, usize), EditError> {
+        let splices = resolve_regex(src, edit)?;
+        Ok((splice(src, &splices), splices.len()))
+    }
+
     fn edit(old: &str, new: &str) -> Edit {
         Edit {
             old: old.into(),
@@ -243,13 +803,22 @@ mod tests {
     }
 
     fn run(text: &str, old: &str, new: &str) -> Result<String, EditError> {
-        apply(text, &edit(old, new))
+        apply(text, &edit(old, new), DEFAULT_FUZZY_THRESHOLD)
+    }
+
+    fn lines(text: &str, start: usize, end: usize, new: &str) -> Result<String, EditError> {
+        let edit = LineEdit {
+            start,
+            end,
+            new: new.into(),
+        };
+        apply_lines(text, &edit).map(|(out, _)| out)
     }
 
     fn apply_seq(text: &str, edits: &[Edit]) -> Result<String, EditError> {
         let mut buf = text.to_string();
         for e in edits {
-            buf = apply(&buf, e)?;
+            buf = apply(&buf, e, DEFAULT_FUZZY_THRESHOLD)?;
         }
         Ok(buf)
     }
@@ -318,6 +887,39 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn not_found_points_at_the_closest_region() {
+        let src = "fn a() {}\nlet s = 'x';\nfn b() {}\n";
+        let err = run(src, "let s = \"x\";", "let s = \"y\";").unwrap_err();
+        assert_eq!(
+            err,
+            EditError::NotFound {
+                old: "let s = \"x\";".into(),
+                closest: Some((2, "let s = 'x';".into())),
+            }
+        );
+        let msg = err.to_string();
+        assert!(msg.contains("line 2"), "{msg}");
+        assert!(msg.contains("-let s = \"x\";\n+let s = 'x';\n"), "{msg}");
+
+        let err = run(src, "completely unrelated text here", "x").unwrap_err();
+        assert!(matches!(err, EditError::NotFound { closest: None, .. }));
+    }
+
+    #[test]
+    fn closest_region_is_quick_on_large_text() {
+        let src: String = (0..3000)
+            .map(|i| format!("    let value_{i} = compute({i}, \"{}\");\n", i * 7))
+            .collect();
+        let old: String = (1200..1240)
+            .map(|i| format!("let value_{i} = compute({i}, '{}');\n", i * 7))
+            .collect();
+        let started = std::time::Instant::now();
+        let got = closest(&src, &old);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(got.map(|(line, _)| line), Some(1201));
+    }
+
     #[test]
     fn empty_old_rejected() {
         assert!(matches!(run("hello", "", "x"), Err(EditError::EmptyOld)));
@@ -370,6 +972,141 @@ mod tests {
         assert_eq!(got, "fn f() { 42 }");
     }
 
+    #[test]
+    fn fuzzy_tolerates_one_misquoted_token() {
+        let src = "match x {\n    0 => 1,\n    _ => x * 2,\n}\n";
+        let old = "0 -> 1,\n_ => x * 2,";
+        let new = "    0 => 1,\n    _ => x * 3,";
+        assert_eq!(
+            run(src, old, new).unwrap(),
+            "match x {\n    0 => 1,\n    _ => x * 3,\n}\n"
+        );
+        assert!(matches!(
+            apply(src, &edit(old, new), 1.0),
+            Err(EditError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn fuzzy_leaves_short_lines_alone() {
+        let src = "if x.len() > 1 {\n    go();\n}\n";
+        assert!(matches!(
+            apply(src, &edit("if x.len() > 0 {", "if !x.is_empty() {"), 0.9),
+            Err(EditError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn fuzzy_is_quick_on_large_text() {
+        let src: String = (0..3000)
+            .map(|i| format!("let value_{i} = compute({i}, \"{}\");\n", i * 7919))
+            .collect();
+        let old = "let value_1500 = compute(1500, '11878500');\nlet value_1501 = compute(1501, \"11886419\");";
+        let started = std::time::Instant::now();
+        let got = fuzzy(&src, old, DEFAULT_FUZZY_THRESHOLD);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(got.len(), 1);
+        assert!(got[0].starts_with("let value_1500 = compute(1500, \"11878500\")"));
+    }
+
+    #[test]
+    fn fuzzy_refuses_when_several_regions_qualify() {
+        let src = "let greeting = \"hello there\";\nlet farewell = \"hello there\";\n";
+        assert!(apply(src, &edit("let greeting = 'hello there';", "x"), 0.8).is_ok());
+        assert!(matches!(
+            apply(src, &edit("let greetin = 'hello there';", "x"), 0.6),
+            Err(EditError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn banded_levenshtein() {
+        let d = |a: &str, b: &str, max| {
+            let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+            levenshtein_within(&a, &b, max)
+        };
+        assert_eq!(d("kitten", "sitting", 3), Some(3));
+        assert_eq!(d("kitten", "sitting", 2), None);
+        assert_eq!(d("", "abc", 3), Some(3));
+        assert_eq!(d("abc", "abc", 0), Some(0));
+        assert_eq!(d("'x'", "\"x\"", 2), Some(2));
+    }
+
+    #[test]
+    fn replacement_follows_the_matched_indentation() {
+        let src = "class A:\n    def f(self):\n        x = 1\n        return x\n";
+        let old = "def f(self):\n    x = 1\n    return x";
+        let new = "def f(self):\n    x = 2\n    return x";
+        assert_eq!(
+            run(src, old, new).unwrap(),
+            "class A:\n    def f(self):\n        x = 2\n        return x\n"
+        );
+    }
+
+    #[test]
+    fn replacement_uses_the_buffers_tabs() {
+        let src = "fn f() {\n\tif a {\n\t\tb();\n\t}\n}\n";
+        let old = "if a {\n    b();\n}";
+        let new = "if a {\n    c();\n    d();\n}";
+        assert_eq!(
+            run(src, old, new).unwrap(),
+            "fn f() {\n\tif a {\n\t\tc();\n\t\td();\n\t}\n}\n"
+        );
+    }
+
+    #[test]
+    fn nested_selection_keeps_its_indent_unit() {
+        let src = "        if a {\n            b();\n        }\n";
+        let old = "if a {\n\tb();\n}";
+        let new = "if a {\n\tc();\n\td();\n}";
+        assert_eq!(
+            run(src, old, new).unwrap(),
+            "        if a {\n            c();\n            d();\n        }\n"
+        );
+        assert_eq!(indent_style(src), Indent::Spaces(4));
+        assert_eq!(indent_unit([8, 12, 16, 12, 8]), 4);
+        assert_eq!(indent_unit([2, 2]), 2);
+    }
+
+    #[test]
+    fn written_spans_follow_later_splices() {
+        let splices = |v: &[(Range<usize>, &str)]| -> Vec<Splice> {
+            v.iter()
+                .map(|(range, new)| Splice {
+                    range: range.clone(),
+                    new: new.to_string(),
+                })
+                .collect()
+        };
+        let mut written = Written::default();
+        let src = "aaa bbb ccc";
+        let first = splices(&[(4..7, "B")]);
+        written.check(src, &first).unwrap();
+        written.record(&first);
+        let src = splice(src, &first);
+        assert_eq!(src, "aaa B ccc");
+
+        let second = splices(&[(0..3, "xxxxx"), (6..9, "C")]);
+        written.check(&src, &second).unwrap();
+        written.record(&second);
+        let src = splice(&src, &second);
+        assert_eq!(src, "xxxxx B C");
+        assert_eq!(written.spans, vec![6..7, 0..5, 8..9]);
+        assert_eq!(written.replaced, vec![4..7, 0..3, 8..11]);
+
+        let touching = splices(&[(5..6, "_")]);
+        assert!(written.check(&src, &touching).is_ok());
+        let overlapping = splices(&[(4..7, "y")]);
+        assert!(matches!(
+            written.check(&src, &overlapping),
+            Err(EditError::Conflict {
+                start_line: 1,
+                end_line: 1,
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn blank_old_does_not_splatter_under_replace_all() {
         let e = Edit {
@@ -378,7 +1115,7 @@ mod tests {
             replace_all: true,
         };
         assert!(matches!(
-            apply("a\n\nb", &e),
+            apply("a\n\nb", &e, DEFAULT_FUZZY_THRESHOLD),
             Err(EditError::NotFound { .. })
         ));
     }
@@ -389,4 +1126,82 @@ mod tests {
         let got = run(src, "    a = 1", "    a = 2").unwrap();
         assert_eq!(got, "  a = 1\n    a = 2\n");
     }
+
+    #[test]
+    fn line_edits_replace_insert_and_delete() {
+        let src = "a\nb\nb\nc\n";
+        let (out, old) = apply_lines(
+            src,
+            &LineEdit {
+                start: 2,
+                end: 3,
+                new: "B".into(),
+            },
+        )
+        .unwrap();
+        assert_eq!((out.as_str(), old.as_str()), ("a\nB\nc\n", "b\nb\n"));
+        assert_eq!(lines(src, 1, 0, "top").unwrap(), "top\na\nb\nb\nc\n");
+        assert_eq!(lines(src, 5, 4, "end\n").unwrap(), "a\nb\nb\nc\nend\n");
+        assert_eq!(lines(src, 4, 4, "").unwrap(), "a\nb\nb\n");
+        assert_eq!(lines("a\nb", 3, 2, "c").unwrap(), "a\nb\nc");
+        assert_eq!(lines("a\nb", 2, 2, "c").unwrap(), "a\nc");
+    }
+
+    #[test]
+    fn bad_line_ranges_are_rejected() {
+        let range = |start, end| EditError::LineRange {
+            start,
+            end,
+            lines: 2,
+        };
+        assert_eq!(lines("a\nb\n", 0, 1, "x"), Err(range(0, 1)));
+        assert_eq!(lines("a\nb\n", 2, 3, "x"), Err(range(2, 3)));
+        assert_eq!(lines("a\nb\n", 3, 1, "x"), Err(range(3, 1)));
+        assert!(matches!(
+            lines("a\nb\n", 1, 1, "a"),
+            Err(EditError::NoChange { .. })
+        ));
+    }
+
+    #[test]
+    fn numbered_lines_are_aligned() {
+        let src: String = (1..=10).map(|i| format!("l{i}\n")).collect();
+        let got = numbered(&src);
+        assert!(got.starts_with(" 1\tl1\n 2\tl2\n"));
+        assert!(got.ends_with("10\tl10\n"));
+        assert_eq!(numbered(""), "");
+    }
+
+    fn regex(pattern: &str, replacement: &str, max_count: Option<usize>) -> RegexEdit {
+        RegexEdit {
+            pattern: pattern.into(),
+            replacement: replacement.into(),
+            max_count,
+        }
+    }
+
+    #[test]
+    fn regex_replaces_with_capture_groups() {
+        let src = "foo_a = foo_b(foo_c)";
+        let (out, n) = apply_regex(src, &regex(r"\bfoo_(\w)", "bar_$1", None)).unwrap();
+        assert_eq!((out.as_str(), n), ("bar_a = bar_b(bar_c)", 3));
+        let (out, n) = apply_regex(src, &regex(r"foo_(?<x>\w)", "${x}_foo", Some(2))).unwrap();
+        assert_eq!((out.as_str(), n), ("a_foo = b_foo(foo_c)", 2));
+    }
+
+    #[test]
+    fn regex_errors_are_reported() {
+        assert!(matches!(
+            apply_regex("abc", &regex("(", "", None)),
+            Err(EditError::BadPattern { .. })
+        ));
+        assert!(matches!(
+            apply_regex("abc", &regex("x+", "y", None)),
+            Err(EditError::NoMatch { .. })
+        ));
+        assert!(matches!(
+            apply_regex("abc", &regex("b", "b", None)),
+            Err(EditError::NoChange { .. })
+        ));
+    }
 }
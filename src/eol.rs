@@ -0,0 +1,109 @@
+use similar::{capture_diff_slices, Algorithm, DiffTag};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Lf,
+    Crlf,
+    Mixed,
+}
+
+impl Style {
+    pub fn detect(s: &str) -> Style {
+        let (lf, crlf) = counts(s);
+        match (lf, crlf) {
+            (_, 0) => Style::Lf,
+            (0, _) => Style::Crlf,
+            _ => Style::Mixed,
+        }
+    }
+}
+
+fn counts(s: &str) -> (usize, usize) {
+    let crlf = s.matches("\r\n").count();
+    (s.matches('\n').count() - crlf, crlf)
+}
+
+// The model and the edit engine only ever see `\n`.
+pub fn normalize(s: &str) -> String {
+    s.replace("\r\n", "\n")
+}
+
+// Converts text the model wrote to the newline most of `original` uses.
+pub fn styled(original: &str, s: &str) -> String {
+    let (lf, crlf) = counts(original);
+    let s = normalize(s);
+    match crlf > lf {
+        true => s.replace('\n', "\r\n"),
+        false => s,
+    }
+}
+
+// Puts `original`'s line endings back on `edited`, its normalized and edited form. Lines
+// that survived the edit keep their own ending when the original was mixed; new lines get
+// the majority one.
+pub fn restore(original: &str, edited: &str) -> String {
+    match Style::detect(original) {
+        Style::Lf => edited.to_string(),
+        Style::Crlf => styled(original, edited),
+        Style::Mixed => {
+            let normalized = normalize(original);
+            let edited = normalize(edited);
+            // Diffed on `\n` alone, since `from_lines` would also split on a lone `\r` and
+            // its ranges then wouldn't index these.
+            let raw: Vec<&str> = original.split_inclusive('\n').collect();
+            let old: Vec<&str> = normalized.split_inclusive('\n').collect();
+            let new: Vec<&str> = edited.split_inclusive('\n').collect();
+            let mut out = String::with_capacity(original.len() + edited.len());
+            for op in capture_diff_slices(Algorithm::Myers, &old, &new) {
+                match op.tag() {
+                    DiffTag::Equal => out.extend(raw[op.old_range()].iter().copied()),
+                    DiffTag::Delete => {}
+                    DiffTag::Insert | DiffTag::Replace => {
+                        for line in &new[op.new_range()] {
+                            out.push_str(&styled(original, line));
+                        }
+                    }
+                }
+            }
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_styles() {
+        assert_eq!(Style::detect("a\nb\n"), Style::Lf);
+        assert_eq!(Style::detect("no newline"), Style::Lf);
+        assert_eq!(Style::detect("a\r\nb\r\n"), Style::Crlf);
+        assert_eq!(Style::detect("a\r\nb\n"), Style::Mixed);
+    }
+
+    #[test]
+    fn crlf_round_trips() {
+        let original = "one\r\ntwo\r\n";
+        let edited = normalize(original).replace("two", "2\nthree");
+        assert_eq!(restore(original, &edited), "one\r\n2\r\nthree\r\n");
+        assert_eq!(styled(original, "x\ny"), "x\r\ny");
+        assert_eq!(restore("a\nb", "a\nc"), "a\nc");
+    }
+
+    #[test]
+    fn mixed_keeps_untouched_lines_as_they_were() {
+        let original = "a\r\nb\nc\r\nd\r\n";
+        let edited = "a\nb\nC\nnew\nd\n";
+        assert_eq!(restore(original, edited), "a\r\nb\nC\r\nnew\r\nd\r\n");
+    }
+
+    #[test]
+    fn mixed_tolerates_a_lone_carriage_return() {
+        let original = "a\rb\r\nc\nd\n";
+        let edited = normalize(original).replace('d', "D");
+        assert_eq!(restore(original, &edited), "a\rb\r\nc\nD\n");
+        let edited = normalize(original).replace('c', "x\ry");
+        assert_eq!(restore(original, &edited), "a\rb\r\nx\ry\nd\n");
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+pub fn read(path: &Path) -> Result<String> {
+    fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))
+}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".orig");
+    PathBuf::from(name)
+}
+
+// Writes `output` over `path`, which must still hold `original`. The first backup is kept,
+// so after `refac again` the `.orig` is still the text before refac touched it.
+pub fn replace(path: &Path, original: &str, output: &str) -> Result<()> {
+    if original == output {
+        return Ok(());
+    }
+    if read(path)? != original {
+        anyhow::bail!(
+            "{} changed while refac was working; refusing to overwrite it (the result is in `refac history`)",
+            path.display()
+        );
+    }
+    let backup = backup_path(path);
+    if !backup.exists() {
+        fs::copy(path, &backup).with_context(|| format!("backing up to {}", backup.display()))?;
+    }
+    write_atomic(path, output).with_context(|| format!("writing {}", path.display()))
+}
+
+fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let dir = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    tmp.write_all(contents.as_bytes())?;
+    tmp.as_file().sync_all()?;
+    if let Ok(meta) = fs::metadata(path) {
+        tmp.as_file().set_permissions(meta.permissions())?;
+    }
+    tmp.persist(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_writes_output_and_keeps_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.rs");
+        fs::write(&path, "old").unwrap();
+        replace(&path, "old", "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "old");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn refuses_a_file_changed_since_it_was_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.rs");
+        fs::write(&path, "saved meanwhile").unwrap();
+        assert!(replace(&path, "old", "new").is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "saved meanwhile");
+        assert!(!backup_path(&path).exists());
+    }
+
+    #[test]
+    fn later_write_backs_keep_the_first_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.rs");
+        fs::write(&path, "old").unwrap();
+        replace(&path, "old", "new").unwrap();
+        replace(&path, "new", "newer").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "newer");
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "old");
+    }
+
+    #[test]
+    fn unchanged_output_leaves_file_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.rs");
+        fs::write(&path, "same").unwrap();
+        replace(&path, "same", "same").unwrap();
+        assert!(!backup_path(&path).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replace_preserves_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.sh");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        replace(&path, "old", "new").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+    }
+}
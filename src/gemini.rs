@@ -0,0 +1,444 @@
+use schemars::Schema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::agent::{Model, RawCall, Seed, Tool, ToolResult, Usage, SEED_TOOL};
+use crate::backend::{by_position, Http};
+use crate::config_files::Config;
+
+const API_URL: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+
+#[derive(Serialize, Deserialize)]
+struct Content {
+    role: Role,
+    parts: Vec<Part>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Role {
+    User,
+    Model,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Part {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    function_call: Option<FunctionCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    function_response: Option<FunctionResponse>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionCall {
+    name: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    args: Value,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    name: String,
+    response: Value,
+}
+
+#[derive(Serialize)]
+struct SystemInstruction {
+    parts: Vec<Part>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolDefs {
+    function_declarations: Vec<FunctionDeclaration>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FunctionDeclaration {
+    name: String,
+    description: String,
+    parameters_json_schema: Schema,
+}
+
+pub struct GeminiAgent {
+    key: String,
+    url: String,
+    http: Http,
+    system: SystemInstruction,
+    contents: Vec<Content>,
+    tools: Vec<ToolDefs>,
+    usage: Usage,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Request<'a> {
+    system_instruction: &'a SystemInstruction,
+    contents: &'a [Content],
+    tools: &'a [ToolDefs],
+    tool_config: Value,
+}
+
+impl GeminiAgent {
+    pub fn new(key: String, model: String, config: &Config, seed: &Seed, tools: &[Tool]) -> Self {
+        let contents = vec![
+            Content {
+                role: Role::User,
+                parts: vec![Part {
+                    text: Some(seed.transform.to_string()),
+                    ..Part::default()
+                }],
+            },
+            Content {
+                role: Role::Model,
+                parts: vec![Part {
+                    function_call: Some(FunctionCall {
+                        name: SEED_TOOL.to_string(),
+                        args: Seed::seed_call_args(),
+                        extra: Map::new(),
+                    }),
+                    ..Part::default()
+                }],
+            },
+            Content {
+                role: Role::User,
+                parts: vec![Part {
+                    function_response: Some(FunctionResponse {
+                        id: None,
+                        name: SEED_TOOL.to_string(),
+                        response: json!({ "output": seed.selected }),
+                    }),
+                    ..Part::default()
+                }],
+            },
+        ];
+        Self::with_contents(key, model, config, seed.system, contents, tools)
+    }
+
+    pub fn resume(
+        key: String,
+        model: String,
+        config: &Config,
+        system: &str,
+        transcript: Value,
+        tools: &[Tool],
+    ) -> anyhow::Result<Self> {
+        let contents = serde_json::from_value(transcript)
+            .map_err(|e| anyhow::anyhow!("saved Gemini conversation did not parse: {e}"))?;
+        Ok(Self::with_contents(
+            key, model, config, system, contents, tools,
+        ))
+    }
+
+    fn with_contents(
+        key: String,
+        model: String,
+        config: &Config,
+        system: &str,
+        contents: Vec<Content>,
+        tools: &[Tool],
+    ) -> Self {
+        let system = SystemInstruction {
+            parts: vec![Part {
+                text: Some(system.to_string()),
+                ..Part::default()
+            }],
+        };
+        let tools = vec![ToolDefs {
+            function_declarations: tools
+                .iter()
+                .map(|t| FunctionDeclaration {
+                    name: t.name.to_string(),
+                    description: t.description.to_string(),
+                    parameters_json_schema: t.input_schema.clone(),
+                })
+                .collect(),
+        }];
+        GeminiAgent {
+            key,
+            url: format!("{API_URL}/{model}:generateContent"),
+            http: Http::new(config),
+            system,
+            contents,
+            tools,
+            usage: Usage::default(),
+        }
+    }
+
+    fn request(&self) -> Request<'_> {
+        Request {
+            system_instruction: &self.system,
+            contents: &self.contents,
+            tools: &self.tools,
+            tool_config: json!({ "functionCallingConfig": { "mode": "AUTO" } }),
+        }
+    }
+
+    // Gemini only sometimes sends ids.
+    fn response_parts(&self, results: Vec<ToolResult>) -> Vec<Part> {
+        let calls: Vec<&FunctionCall> = match self.contents.last() {
+            Some(Content {
+                role: Role::Model,
+                parts,
+            }) => parts
+                .iter()
+                .filter_map(|p| p.function_call.as_ref())
+                .collect(),
+            _ => Vec::new(),
+        };
+        by_position(&calls, results)
+            .map(|(call, r)| {
+                let response = match r.result {
+                    Ok(c) => json!({ "output": c }),
+                    Err(c) => json!({ "error": c }),
+                };
+                Part {
+                    function_response: Some(FunctionResponse {
+                        id: call.and_then(|c| c.extra.get("id")?.as_str().map(String::from)),
+                        name: call.map(|c| c.name.clone()).unwrap_or_default(),
+                        response,
+                    }),
+                    ..Part::default()
+                }
+            })
+            .collect()
+    }
+}
+
+impl Model for GeminiAgent {
+    fn turn(&mut self, results: Vec<ToolResult>) -> anyhow::Result<Vec<RawCall>> {
+        if !results.is_empty() {
+            let parts = self.response_parts(results);
+            self.contents.push(Content {
+                role: Role::User,
+                parts,
+            });
+        }
+
+        let body = post(&self.http, &self.url, &self.key, &self.request())?;
+        self.usage += usage_from_body(&body);
+        let content = body["candidates"][0]["content"].clone();
+        if content.is_null() {
+            anyhow::bail!("Gemini response missing content: {body}");
+        }
+        let content: Content = serde_json::from_value(content)
+            .map_err(|e| anyhow::anyhow!("Gemini content did not parse: {e}"))?;
+        let calls = raw_calls(&content.parts);
+        self.contents.push(content);
+        Ok(calls)
+    }
+
+    fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    fn transcript(&self) -> anyhow::Result<Value> {
+        Ok(serde_json::to_value(&self.contents)?)
+    }
+
+    fn follow_up(&mut self, results: Vec<ToolResult>, transform: &str) -> anyhow::Result<()> {
+        let mut parts = self.response_parts(results);
+        parts.push(Part {
+            text: Some(transform.to_string()),
+            ..Part::default()
+        });
+        self.contents.push(Content {
+            role: Role::User,
+            parts,
+        });
+        Ok(())
+    }
+}
+
+fn raw_calls(parts: &[Part]) -> Vec<RawCall> {
+    parts
+        .iter()
+        .filter_map(|p| p.function_call.as_ref())
+        .enumerate()
+        .map(|(i, c)| RawCall {
+            id: match c.extra.get("id").and_then(Value::as_str) {
+                Some(id) => id.to_string(),
+                None => format!("call_{i}"),
+            },
+            name: c.name.clone(),
+            args: match &c.args {
+                Value::Null => json!({}),
+                args => args.clone(),
+            },
+        })
+        .collect()
+}
+
+fn usage_from_body(body: &Value) -> Usage {
+    let count = |k: &str| body["usageMetadata"][k].as_u64().unwrap_or(0);
+    Usage {
+        input_tokens: count("promptTokenCount"),
+        output_tokens: count("candidatesTokenCount") + count("thoughtsTokenCount"),
+        ..Usage::default()
+    }
+}
+
+fn post(http: &Http, url: &str, key: &str, req: &Request) -> anyhow::Result<Value> {
+    http.send_json(http.post(url).header("x-goog-api-key", key).json(req))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent() -> GeminiAgent {
+        let tools = crate::agent::tools();
+        let seed = Seed {
+            system: "SYS",
+            selected: "selected",
+            transform: "transform",
+        };
+        GeminiAgent::new(
+            "k".into(),
+            "gemini-2.5-pro".into(),
+            &Config::default(),
+            &seed,
+            &tools,
+        )
+    }
+
+    fn request_json(agent: &GeminiAgent) -> Value {
+        serde_json::to_value(agent.request()).unwrap()
+    }
+
+    #[test]
+    fn agent_request_carries_tools_and_seed() {
+        let agent = agent();
+        assert!(agent
+            .url
+            .ends_with("/models/gemini-2.5-pro:generateContent"));
+        let req = request_json(&agent);
+
+        assert_eq!(req["systemInstruction"]["parts"][0]["text"], "SYS");
+        assert_eq!(req["contents"][0]["role"], "user");
+        assert_eq!(
+            req["contents"][0]["parts"][0],
+            json!({ "text": "transform" })
+        );
+        assert_eq!(req["contents"][1]["role"], "model");
+        assert_eq!(
+            req["contents"][1]["parts"][0],
+            json!({ "functionCall": { "name": "view", "args": {} } })
+        );
+        assert_eq!(req["contents"][2]["role"], "user");
+        assert_eq!(
+            req["contents"][2]["parts"][0],
+            json!({ "functionResponse": { "name": "view", "response": { "output": "selected" } } })
+        );
+        assert_eq!(req["toolConfig"]["functionCallingConfig"]["mode"], "AUTO");
+        let names: Vec<&str> = req["tools"][0]["functionDeclarations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "edit",
+                "edit_lines",
+                "regex_replace",
+                "view",
+                "reset",
+                "finish"
+            ]
+        );
+        assert!(req["tools"][0]["functionDeclarations"][0]["parametersJsonSchema"].is_object());
+    }
+
+    #[test]
+    fn model_turn_is_echoed_verbatim_and_answered_by_name() {
+        let mut agent = agent();
+        let raw = json!({
+            "role": "model",
+            "parts": [
+                { "text": "on it" },
+                { "functionCall": { "name": "edit", "args": { "old": "a", "new": "b" } },
+                  "thoughtSignature": "sig" },
+                { "functionCall": { "id": "fc_2", "name": "finish" } }
+            ]
+        });
+        let content: Content = serde_json::from_value(raw.clone()).unwrap();
+        let calls = raw_calls(&content.parts);
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "call_0");
+        assert_eq!(calls[0].args["old"], "a");
+        assert_eq!(calls[1].id, "fc_2");
+        assert_eq!(calls[1].args, json!({}));
+        agent.contents.push(content);
+        assert_eq!(request_json(&agent)["contents"][3], raw);
+
+        agent
+            .follow_up(
+                vec![
+                    ToolResult {
+                        id: "call_0".into(),
+                        result: Err("nope".into()),
+                    },
+                    ToolResult {
+                        id: "fc_2".into(),
+                        result: Ok("finished".into()),
+                    },
+                ],
+                "more",
+            )
+            .unwrap();
+        let req = request_json(&agent);
+        let parts = &req["contents"][4]["parts"];
+        assert_eq!(req["contents"][4]["role"], "user");
+        assert_eq!(
+            parts[0],
+            json!({ "functionResponse": { "name": "edit", "response": { "error": "nope" } } })
+        );
+        assert_eq!(parts[1]["functionResponse"]["id"], "fc_2");
+        assert_eq!(parts[1]["functionResponse"]["name"], "finish");
+        assert_eq!(parts[2], json!({ "text": "more" }));
+    }
+
+    #[test]
+    fn resumed_transcript_round_trips() {
+        let agent = agent();
+        let tools = crate::agent::tools();
+        let transcript = agent.transcript().unwrap();
+        let resumed = GeminiAgent::resume(
+            "k".into(),
+            "gemini-2.5-pro".into(),
+            &Config::default(),
+            "SYS",
+            transcript,
+            &tools,
+        )
+        .unwrap();
+        assert_eq!(request_json(&resumed), request_json(&agent));
+    }
+
+    #[test]
+    fn usage_counts_thoughts_as_output() {
+        let body = json!({ "usageMetadata": {
+            "promptTokenCount": 12, "candidatesTokenCount": 3, "thoughtsTokenCount": 4
+        } });
+        assert_eq!(
+            usage_from_body(&body),
+            Usage {
+                input_tokens: 12,
+                output_tokens: 7,
+                ..Usage::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::agent::Usage;
+use crate::config_files::Provider;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<u64>,
+    pub provider: Provider,
+    pub model: String,
+    pub selected: String,
+    pub transform: String,
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Estimated, in USD; absent when the model has no configured price.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+pub fn load() -> Result<Vec<(usize, LogEntry)>> {
+    match std::fs::read_to_string(crate::log_location("logs")?) {
+        Ok(contents) => Ok(parse(&contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn find(id: usize) -> Result<LogEntry> {
+    load()?
+        .into_iter()
+        .find(|(i, _)| *i == id)
+        .map(|(_, e)| e)
+        .ok_or_else(|| anyhow::anyhow!("no history entry {id}; see `refac history`"))
+}
+
+fn parse(contents: &str) -> Vec<(usize, LogEntry)> {
+    contents
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| Some((i + 1, serde_json::from_str(line).ok()?)))
+        .collect()
+}
+
+pub fn when(entry: &LogEntry) -> String {
+    match entry.timestamp {
+        Some(t) => {
+            humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(t)).to_string()
+        }
+        None => "-".to_string(),
+    }
+}
+
+pub fn summary(id: usize, entry: &LogEntry) -> String {
+    format!(
+        "{id:>5}  {:<20}  {:?}/{}  {}",
+        when(entry),
+        entry.provider,
+        entry.model,
+        truncate(&entry.transform, 60)
+    )
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    let line = s.lines().next().unwrap_or("");
+    if line.chars().count() > max || line.len() < s.trim_end().len() {
+        let cut: String = line.chars().take(max.saturating_sub(1)).collect();
+        format!("{cut}…")
+    } else {
+        line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_line_numbers_and_bad_lines_are_skipped() {
+        let contents = concat!(
+            r#"{"provider":"anthropic","model":"m","selected":"a","transform":"t","output":"b"}"#,
+            "\nnot json\n",
+            r#"{"timestamp":0,"session":7,"provider":"openai","model":"g","selected":"c","transform":"u","output":"d"}"#,
+            "\n",
+        );
+        let got = parse(contents);
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].0, 1);
+        assert_eq!(got[0].1.timestamp, None);
+        assert_eq!(got[1].0, 3);
+        assert_eq!(got[1].1.selected, "c");
+        assert_eq!(got[1].1.session, Some(7));
+        assert_eq!(when(&got[1].1), "1970-01-01T00:00:00Z");
+        assert_eq!(when(&got[0].1), "-");
+    }
+
+    #[test]
+    fn truncate_keeps_the_first_line() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("abcdefghijkl", 5), "abcd…");
+        assert_eq!(truncate("first\nsecond", 60), "first…");
+        assert_eq!(truncate("", 60), "");
+    }
+}
@@ -1,18 +1,33 @@
 mod agent;
 mod anthropic;
 mod backend;
+mod cassette;
 mod config_files;
+mod diff;
 mod edit;
+mod eol;
+mod files;
+mod gemini;
+mod history;
+mod ollama;
 mod openai;
+mod openai_responses;
+mod progress;
 mod prompt;
+mod review;
+mod session;
+mod stats;
+mod syntax;
 
 use anyhow::Context;
 use clap::Parser;
 use config_files::{Config, Provider, Secrets};
+use history::LogEntry;
 use serde::Serialize;
+use session::Session;
 use std::{
     fs::{create_dir_all, OpenOptions},
-    io::Write,
+    io::{self, Write},
     path::PathBuf,
     sync::Once,
 };
@@ -25,13 +40,78 @@ struct Opts {
     subcmd: SubCommand,
 }
 
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Format {
+    /// The transformed text.
+    Text,
+    /// A unified diff from the selection to the transformed text.
+    Diff,
+    /// A JSON object with the transformed text, every edit attempted, and token usage.
+    Json,
+}
+
 #[derive(Parser)]
 enum SubCommand {
     Login {
         #[clap(long)]
         provider: Option<Provider>,
     },
-    Tor { selected: String, transform: String },
+    Tor {
+        /// The text to transform, or `-` to read it from stdin. Omit it when using --file.
+        selected: Option<String>,
+        transform: Option<String>,
+        /// Read the text to transform from this file.
+        #[clap(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+        #[clap(long, visible_alias = "output", value_enum, default_value_t = Format::Text)]
+        format: Format,
+        /// Accept, reject, or follow up on each changed hunk before output.
+        #[clap(long)]
+        review: bool,
+        /// Check that the result still parses in this language; guessed from the file's extension.
+        #[clap(long, value_enum)]
+        lang: Option<syntax::Lang>,
+    },
+    /// Transform a file in place, keeping the original next to it as `<path>.orig`.
+    File {
+        path: PathBuf,
+        transform: String,
+        /// Accept, reject, or follow up on each changed hunk before writing.
+        #[clap(long)]
+        review: bool,
+        /// Check that the result still parses in this language; guessed from the file's extension.
+        #[clap(long, value_enum)]
+        lang: Option<syntax::Lang>,
+    },
+    /// Continue the last session with a follow-up transform.
+    Again {
+        transform: String,
+        /// Continue this session instead of the most recent one.
+        #[clap(long)]
+        session: Option<u64>,
+        #[clap(long, visible_alias = "output", value_enum, default_value_t = Format::Text)]
+        format: Format,
+        /// Accept, reject, or follow up on each changed hunk before output.
+        #[clap(long)]
+        review: bool,
+        /// Check that the result still parses in this language; guessed from the file's extension.
+        #[clap(long, value_enum)]
+        lang: Option<syntax::Lang>,
+    },
+    /// List recent transforms.
+    History {
+        #[clap(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Show a history entry in full.
+    Show { id: usize },
+    /// Print the original selection of a history entry.
+    Undo { id: usize },
+    /// Summarize token usage and estimated cost from the history.
+    Stats {
+        #[clap(long, value_enum, default_value = "day")]
+        by: stats::GroupBy,
+    },
 }
 
 fn main() {
@@ -39,6 +119,7 @@ fn main() {
     match run() {
         Ok(()) => {}
         Err(e) => {
+            progress::clear();
             eprintln!("{e:?}");
             std::process::exit(1);
         }
@@ -54,7 +135,7 @@ fn run() -> anyhow::Result<()> {
             let provider = match provider {
                 Some(p) => p,
                 None => {
-                    let choices = [Provider::Anthropic, Provider::Openai];
+                    let choices = [Provider::Anthropic, Provider::Openai, Provider::Gemini];
                     let labels: Vec<String> = choices.iter().map(|p| format!("{p:?}")).collect();
                     let idx = dialoguer::Select::new()
                         .with_prompt("Which provider?")
@@ -75,79 +156,392 @@ fn run() -> anyhow::Result<()> {
                     let api_key = rpassword::prompt_password("Enter your OpenAI API key:")?;
                     secrets.openai_api_key = Some(api_key);
                 }
+                Provider::Gemini => {
+                    println!("https://aistudio.google.com/apikey");
+                    let api_key = rpassword::prompt_password("Enter your Gemini API key:")?;
+                    secrets.gemini_api_key = Some(api_key);
+                }
+                Provider::Ollama => {
+                    println!("Ollama runs locally and needs no API key.");
+                    return Ok(());
+                }
             }
             secrets.save()?;
         }
         SubCommand::Tor {
             selected,
             transform,
+            file,
+            format,
+            review,
+            lang,
+        } => {
+            let name = file
+                .as_ref()
+                .map_or("selection".into(), |p| p.display().to_string());
+            let lang = lang.or_else(|| file.as_deref().and_then(syntax::Lang::from_path));
+            let (selected, transform) = selection(selected, transform, file)?;
+            let secrets = Secrets::load()?;
+            let config = Config::load()?;
+            let mut refactored = refactor(selected.clone(), transform, lang, &secrets, &config)?;
+            if review {
+                refactored.review(review::ask, lang, &secrets, &config)?;
+            }
+            refactored.record()?;
+            refactored.save_session(None)?;
+            emit(format, &name, &selected, &refactored)?;
+        }
+        SubCommand::File {
+            path,
+            transform,
+            review,
+            lang,
         } => {
             let secrets = Secrets::load()?;
             let config = Config::load()?;
-            let completion = refactor(selected, transform, &secrets, &config)?;
-            print!("{completion}");
+            let lang = lang.or_else(|| syntax::Lang::from_path(&path));
+            let selected = files::read(&path)?;
+            let mut refactored = refactor(selected.clone(), transform, lang, &secrets, &config)?;
+            if review {
+                refactored.review(review::ask, lang, &secrets, &config)?;
+            }
+            refactored.record()?;
+            files::replace(&path, &selected, &refactored.text)?;
+            refactored.save_session(Some(path.canonicalize()?))?;
         }
+        SubCommand::Again {
+            transform,
+            session,
+            format,
+            review,
+            lang,
+        } => {
+            let secrets = Secrets::load()?;
+            let config = Config::load()?;
+            let session = Session::load(session)?;
+            let path = session.path.clone();
+            let lang = lang.or_else(|| path.as_deref().and_then(syntax::Lang::from_path));
+            let selected = session.text.clone();
+            if let Some(path) = &path {
+                if files::read(path)? != selected {
+                    anyhow::bail!(
+                        "{} changed since the last session; refusing to overwrite it",
+                        path.display()
+                    );
+                }
+            }
+            let mut refactored = again(session, transform, lang, &secrets, &config)?;
+            if review {
+                refactored.review(review::ask, lang, &secrets, &config)?;
+            }
+            refactored.record()?;
+            match &path {
+                Some(path) => files::replace(path, &selected, &refactored.text)?,
+                None => emit(format, "selection", &selected, &refactored)?,
+            }
+            refactored.save_session(path)?;
+        }
+        SubCommand::History { limit } => {
+            let entries = history::load()?;
+            for (id, entry) in &entries[entries.len().saturating_sub(limit)..] {
+                println!("{}", history::summary(*id, entry));
+            }
+        }
+        SubCommand::Show { id } => {
+            let entry = history::find(id)?;
+            println!("time:      {}", history::when(&entry));
+            if let Some(session) = entry.session {
+                println!("session:   {session}");
+            }
+            println!("provider:  {:?}", entry.provider);
+            println!("model:     {}", entry.model);
+            println!("transform: {}", entry.transform);
+            if let Some(u) = entry.usage {
+                println!(
+                    "tokens:    {} in, {} out, {} cache read, {} cache write",
+                    u.input_tokens, u.output_tokens, u.cache_read_tokens, u.cache_write_tokens
+                );
+            }
+            if let Some(cost) = entry.cost {
+                println!("cost:      ${cost:.4}");
+            }
+            println!("--- selected ---\n{}", entry.selected);
+            println!("--- output ---\n{}", entry.output);
+        }
+        SubCommand::Stats { by } => {
+            let entries = history::load()?;
+            print!(
+                "{}",
+                stats::render(&stats::group(entries.iter().map(|(_, e)| e), by))
+            );
+        }
+        SubCommand::Undo { id } => print!("{}", history::find(id)?.selected),
     };
 
     Ok(())
 }
 
+fn selection(
+    selected: Option<String>,
+    transform: Option<String>,
+    file: Option<PathBuf>,
+) -> anyhow::Result<(String, String)> {
+    match (file, selected, transform) {
+        (Some(path), Some(transform), None) => Ok((files::read(&path)?, transform)),
+        (None, Some(selected), Some(transform)) if selected == "-" => {
+            let selected = io::read_to_string(io::stdin()).context("reading stdin")?;
+            Ok((selected, transform))
+        }
+        (None, Some(selected), Some(transform)) => Ok((selected, transform)),
+        (Some(_), _, _) => anyhow::bail!("usage: refac tor --file <PATH> <TRANSFORM>"),
+        (None, _, _) => anyhow::bail!("usage: refac tor <SELECTED> <TRANSFORM>"),
+    }
+}
+
 fn refactor(
     selected: String,
     transform: String,
+    lang: Option<syntax::Lang>,
     sc: &Secrets,
     config: &Config,
-) -> anyhow::Result<String> {
+) -> anyhow::Result<Refactored> {
     let provider = config.provider(sc);
     let model = config.model(provider);
 
     let seed_selected = agent::placeholder_if_empty(&selected).to_owned();
+    let seed_view = eol::normalize(&seed_selected);
     let seed = agent::Seed {
         system: prompt::SYSTEM_PROMPT,
-        selected: &seed_selected,
+        selected: &seed_view,
         transform: agent::placeholder_if_empty(&transform),
     };
     let tools = agent::tools();
-    let mut model_agent = backend::resolve_agent(provider, &model, sc, &seed, &tools)?;
+    let mut model_agent = backend::resolve_agent(provider, &model, config, sc, &seed, &tools)?;
 
     let outcome = agent::run(
         model_agent.as_mut(),
-        seed_selected,
-        agent::DEFAULT_MAX_TURNS,
+        seed_selected.clone(),
+        &agent_options(config, lang),
     )?;
 
-    for attempt in &outcome.attempts {
-        let _ = log(
-            EditLog {
-                provider,
-                model: model.clone(),
-                old: attempt.edit.old.clone(),
-                new: attempt.edit.new.clone(),
-                error: attempt.error.as_ref().map(|e| e.to_string()),
-            },
-            "edits",
-        );
-    }
-    let output = outcome.text;
+    let session = Session {
+        id: session::new_id(),
+        provider,
+        model,
+        original: seed_selected,
+        text: String::new(),
+        path: None,
+        pending: Vec::new(),
+        transcript: serde_json::Value::Null,
+        edited: false,
+    };
+    conclude(
+        model_agent.as_ref(),
+        outcome,
+        session,
+        selected,
+        transform,
+        config,
+    )
+}
 
-    log(
-        LogEntry {
-            provider,
-            model,
-            selected,
-            transform,
-            output: output.clone(),
-        },
-        "logs",
+fn again(
+    session: Session,
+    transform: String,
+    lang: Option<syntax::Lang>,
+    sc: &Secrets,
+    config: &Config,
+) -> anyhow::Result<Refactored> {
+    let tools = agent::tools();
+    let mut model_agent = backend::resume_agent(
+        session.provider,
+        &session.model,
+        config,
+        sc,
+        prompt::SYSTEM_PROMPT,
+        session.transcript.clone(),
+        &tools,
+    )?;
+    let message = session.follow_up(agent::placeholder_if_empty(&transform));
+    model_agent.follow_up(session.pending, &message)?;
+
+    let outcome = agent::resume(
+        model_agent.as_mut(),
+        &session.original,
+        session.text.clone(),
+        &agent_options(config, lang),
     )?;
 
-    Ok(output)
+    let selected = session.text;
+    let session = Session {
+        text: String::new(),
+        pending: Vec::new(),
+        transcript: serde_json::Value::Null,
+        edited: false,
+        ..session
+    };
+    conclude(
+        model_agent.as_ref(),
+        outcome,
+        session,
+        selected,
+        transform,
+        config,
+    )
+}
+
+fn agent_options(config: &Config, lang: Option<syntax::Lang>) -> agent::Options {
+    agent::Options {
+        fuzzy_threshold: config
+            .fuzzy_threshold
+            .unwrap_or(edit::DEFAULT_FUZZY_THRESHOLD),
+        lang,
+        ..agent::Options::default()
+    }
+}
+
+fn conclude(
+    model_agent: &dyn agent::Model,
+    outcome: agent::Outcome,
+    mut session: Session,
+    selected: String,
+    transform: String,
+    config: &Config,
+) -> anyhow::Result<Refactored> {
+    progress::clear();
+    let provider = session.provider;
+    let model = session.model.clone();
+    let usage = model_agent.usage();
+    let cost = config
+        .price(provider, &model)
+        .map(|price| stats::cost(&price, &usage));
+
+    session.text = outcome.text.clone();
+    session.pending = outcome.pending;
+    session.transcript = model_agent.transcript()?;
+
+    Ok(Refactored {
+        text: outcome.text,
+        attempts: outcome.attempts,
+        turns: outcome.turns,
+        provider,
+        model,
+        usage,
+        cost,
+        syntax_errors: outcome.syntax_errors,
+        session,
+        selected,
+        transform,
+    })
+}
+
+fn emit(format: Format, name: &str, selected: &str, refactored: &Refactored) -> anyhow::Result<()> {
+    match format {
+        Format::Text => print!("{}", refactored.text),
+        Format::Diff => print!("{}", diff::unified(selected, &refactored.text, name)),
+        Format::Json => println!("{}", serde_json::to_string(refactored)?),
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Refactored {
+    text: String,
+    attempts: Vec<agent::Attempt>,
+    turns: usize,
+    provider: Provider,
+    model: String,
+    usage: agent::Usage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cost: Option<f64>,
+    /// Set when the result doesn't parse though the selection did.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    syntax_errors: Vec<String>,
+    #[serde(skip)]
+    session: Session,
+    #[serde(skip)]
+    selected: String,
+    #[serde(skip)]
+    transform: String,
+}
+
+impl Refactored {
+    /// Lets `decide` step through the changed hunks. Follow-up runs count towards this
+    /// run's attempts, turns, usage and cost.
+    fn review(
+        &mut self,
+        decide: impl FnMut(usize, usize, &str, &str) -> anyhow::Result<review::Decision>,
+        lang: Option<syntax::Lang>,
+        sc: &Secrets,
+        config: &Config,
+    ) -> anyhow::Result<()> {
+        let mut follow_ups = Vec::new();
+        self.text = review::review(&self.selected, &self.text, decide, |hunk, transform| {
+            let refactored = refactor(hunk.to_string(), transform, lang, sc, config)?;
+            let text = refactored.text.clone();
+            follow_ups.push(refactored);
+            Ok(text)
+        })?;
+        for r in follow_ups {
+            self.attempts.extend(r.attempts);
+            self.turns += r.turns;
+            self.usage += r.usage;
+            self.cost = self.cost.zip(r.cost).map(|(a, b)| a + b);
+        }
+        self.syntax_errors = match lang {
+            Some(lang) if syntax::errors(lang, &self.selected).is_empty() => {
+                syntax::errors(lang, &self.text)
+            }
+            _ => Vec::new(),
+        };
+        Ok(())
+    }
+
+    /// Appends this run to the edit and history logs.
+    fn record(&self) -> anyhow::Result<()> {
+        for attempt in &self.attempts {
+            let _ = log(
+                EditLog {
+                    provider: self.provider,
+                    model: self.model.clone(),
+                    tool: attempt.tool,
+                    old: attempt.edit.old.clone(),
+                    new: attempt.edit.new.clone(),
+                    error: attempt.error.as_ref().map(|e| e.to_string()),
+                },
+                "edits",
+            );
+        }
+
+        log(
+            LogEntry {
+                timestamp: Some(history::now()),
+                session: Some(self.session.id),
+                provider: self.provider,
+                model: self.model.clone(),
+                selected: self.selected.clone(),
+                transform: self.transform.clone(),
+                output: self.text.clone(),
+                usage: Some(self.usage),
+                cost: self.cost,
+            },
+            "logs",
+        )
+    }
+
+    fn save_session(&mut self, path: Option<PathBuf>) -> anyhow::Result<()> {
+        self.session.edited = self.session.text != self.text;
+        self.session.text = self.text.clone();
+        self.session.path = path;
+        self.session.save()
+    }
 }
 
 #[derive(Debug, Serialize)]
 struct EditLog {
     provider: Provider,
     model: String,
+    tool: &'static str,
     old: String,
     new: String,
     error: Option<String>,
@@ -167,15 +561,6 @@ fn log_location(title: &str) -> anyhow::Result<PathBuf> {
     Ok(ret)
 }
 
-#[derive(Debug, Serialize)]
-struct LogEntry {
-    provider: Provider,
-    model: String,
-    selected: String,
-    transform: String,
-    output: String,
-}
-
 fn log<T: Serialize>(t: T, title: &str) -> anyhow::Result<()> {
     fn inner<T: Serialize>(t: T, title: &str) -> anyhow::Result<()> {
         let mut file = OpenOptions::new()
@@ -190,3 +575,135 @@ fn log<T: Serialize>(t: T, title: &str) -> anyhow::Result<()> {
 
     inner(t, title).with_context(|| format!("failed to log {title}"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::tests::{response, serve};
+
+    // One streamed OpenAI turn that makes a single tool call.
+    fn call(id: &str, name: &str, arguments: &str) -> String {
+        let chunk = serde_json::json!({ "choices": [{ "delta": { "tool_calls": [
+            { "index": 0, "id": id, "type": "function",
+              "function": { "name": name, "arguments": arguments } }
+        ] }, "finish_reason": "tool_calls" }],
+          "usage": { "prompt_tokens": 10, "completion_tokens": 1 } });
+        let body = format!("data: {chunk}\n\ndata: [DONE]\n\n");
+        response("200 OK", "content-type: text/event-stream\r\n", &body)
+    }
+
+    #[test]
+    fn cli_is_well_formed() {
+        use clap::CommandFactory;
+        Opts::command().debug_assert();
+    }
+
+    #[test]
+    fn selection_from_argv() {
+        let got = selection(Some("text".into()), Some("caps".into()), None).unwrap();
+        assert_eq!(got, ("text".to_string(), "caps".to_string()));
+    }
+
+    #[test]
+    fn selection_from_file() {
+        let path = std::env::temp_dir().join(format!("refac-selection-{}", std::process::id()));
+        std::fs::write(&path, "from disk").unwrap();
+        let got = selection(Some("caps".into()), None, Some(path.clone()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(got.unwrap(), ("from disk".to_string(), "caps".to_string()));
+    }
+
+    #[test]
+    fn file_and_argv_selection_conflict() {
+        let path = PathBuf::from("x.rs");
+        assert!(selection(Some("a".into()), Some("b".into()), Some(path)).is_err());
+        assert!(selection(Some("a".into()), None, None).is_err());
+    }
+
+    #[test]
+    fn refactor_replays_a_recorded_cassette() {
+        use crate::cassette::Mode;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cassette = dir.path().join("cassette.json");
+        let (url, server) = serve(vec![
+            call("c1", "edit", r#"{"old":"fox","new":"cat"}"#),
+            call("c2", "finish", "{}"),
+        ]);
+        let config = |mode| Config {
+            provider: Some(Provider::Openai),
+            base_url: Some(url.clone()),
+            cassette: Some(mode),
+            ..Config::default()
+        };
+        let run = |mode| {
+            refactor(
+                "The quick brown fox".into(),
+                "make it a cat".into(),
+                None,
+                &Secrets::default(),
+                &config(mode),
+            )
+            .unwrap()
+        };
+
+        let recorded = run(Mode::Record(cassette.clone()));
+        assert_eq!(recorded.text, "The quick brown cat");
+        assert_eq!(server.join().unwrap(), 2);
+
+        // The server has shut down, so this only passes if nothing goes over the wire.
+        let replayed = run(Mode::Replay(cassette));
+        assert_eq!(replayed.text, recorded.text);
+        assert_eq!(replayed.turns, recorded.turns);
+        assert_eq!(
+            serde_json::to_value(&replayed.attempts).unwrap(),
+            serde_json::to_value(&recorded.attempts).unwrap()
+        );
+    }
+
+    #[test]
+    fn review_follow_ups_count_towards_the_run() {
+        let (url, server) = serve(vec![
+            call("c1", "edit", r#"{"old":"fox","new":"cat"}"#),
+            call("c2", "finish", "{}"),
+            call("c3", "edit", r#"{"old":"cat","new":"dog"}"#),
+            call("c4", "finish", "{}"),
+        ]);
+        let config = Config {
+            provider: Some(Provider::Openai),
+            base_url: Some(url),
+            prices: [(
+                "gpt-5.5".to_string(),
+                config_files::Price {
+                    input: 1.0,
+                    output: 1.0,
+                    ..Default::default()
+                },
+            )]
+            .into(),
+            ..Config::default()
+        };
+        let sc = Secrets::default();
+        let selected = "The quick brown fox".to_string();
+        let mut refactored =
+            refactor(selected, "make it a cat".into(), None, &sc, &config).unwrap();
+        let before = refactored.cost.unwrap();
+
+        let mut asked = 0;
+        let decide = |_: usize, _: usize, _: &str, _: &str| {
+            asked += 1;
+            Ok(match asked {
+                1 => review::Decision::FollowUp("make it a dog".into()),
+                _ => review::Decision::Accept,
+            })
+        };
+        refactored.review(decide, None, &sc, &config).unwrap();
+        assert_eq!(server.join().unwrap(), 4);
+
+        assert_eq!(refactored.text, "The quick brown dog");
+        assert_eq!(refactored.turns, 4);
+        assert_eq!(refactored.attempts.len(), 2);
+        assert_eq!(refactored.usage.input_tokens, 40);
+        assert_eq!(refactored.cost, Some(before * 2.0));
+    }
+}
@@ -0,0 +1,376 @@
+use schemars::Schema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::agent::{Model, RawCall, Seed, Tool, ToolResult, Usage, SEED_TOOL};
+use crate::backend::{by_position, Http};
+use crate::config_files::Config;
+
+const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "role", rename_all = "snake_case")]
+enum Message {
+    System { content: String },
+    User { content: String },
+    Tool { tool_name: String, content: String },
+    Assistant(AssistantTurn),
+}
+
+#[derive(Serialize, Deserialize)]
+struct ToolCall {
+    function: FunctionCall,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionCall {
+    name: String,
+    arguments: Value,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AssistantTurn {
+    #[serde(default, skip_serializing)]
+    #[allow(dead_code)]
+    role: Option<String>,
+    #[serde(default)]
+    content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+struct ToolDef {
+    #[serde(rename = "type")]
+    kind: FunctionType,
+    function: FunctionDef,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FunctionType {
+    Function,
+}
+
+#[derive(Serialize)]
+struct FunctionDef {
+    name: String,
+    description: String,
+    parameters: Schema,
+}
+
+pub struct OllamaAgent {
+    model: String,
+    url: String,
+    http: Http,
+    messages: Vec<Message>,
+    tools: Vec<ToolDef>,
+    usage: Usage,
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    tools: &'a [ToolDef],
+    stream: bool,
+}
+
+impl OllamaAgent {
+    pub fn new(model: String, config: &Config, seed: &Seed, tools: &[Tool]) -> Self {
+        let messages = vec![
+            Message::System {
+                content: seed.system.to_string(),
+            },
+            Message::User {
+                content: seed.transform.to_string(),
+            },
+            Message::Assistant(AssistantTurn {
+                role: None,
+                content: String::new(),
+                tool_calls: vec![ToolCall {
+                    function: FunctionCall {
+                        name: SEED_TOOL.to_string(),
+                        arguments: Seed::seed_call_args(),
+                        extra: Map::new(),
+                    },
+                    extra: Map::new(),
+                }],
+                extra: Map::new(),
+            }),
+            Message::Tool {
+                tool_name: SEED_TOOL.to_string(),
+                content: seed.selected.to_string(),
+            },
+        ];
+        Self::with_messages(model, config, messages, tools)
+    }
+
+    pub fn resume(
+        model: String,
+        config: &Config,
+        transcript: Value,
+        tools: &[Tool],
+    ) -> anyhow::Result<Self> {
+        let messages = serde_json::from_value(transcript)
+            .map_err(|e| anyhow::anyhow!("saved Ollama conversation did not parse: {e}"))?;
+        Ok(Self::with_messages(model, config, messages, tools))
+    }
+
+    fn with_messages(
+        model: String,
+        config: &Config,
+        messages: Vec<Message>,
+        tools: &[Tool],
+    ) -> Self {
+        let base_url = config.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL);
+        let url = format!("{}/api/chat", base_url.trim_end_matches('/'));
+        let tools = tools
+            .iter()
+            .map(|t| ToolDef {
+                kind: FunctionType::Function,
+                function: FunctionDef {
+                    name: t.name.to_string(),
+                    description: t.description.to_string(),
+                    parameters: t.input_schema.clone(),
+                },
+            })
+            .collect();
+        OllamaAgent {
+            model,
+            url,
+            http: Http::new(config),
+            messages,
+            tools,
+            usage: Usage::default(),
+        }
+    }
+
+    fn request(&self) -> Request<'_> {
+        Request {
+            model: &self.model,
+            messages: &self.messages,
+            tools: &self.tools,
+            stream: false,
+        }
+    }
+
+    // Ollama tool calls carry no ids.
+    fn push_results(&mut self, results: Vec<ToolResult>) {
+        let names: Vec<String> = match self.messages.last() {
+            Some(Message::Assistant(turn)) => turn
+                .tool_calls
+                .iter()
+                .map(|c| c.function.name.clone())
+                .collect(),
+            _ => Vec::new(),
+        };
+        for (name, r) in by_position(&names, results) {
+            let content = match r.result {
+                Ok(c) => c,
+                Err(c) => format!("ERROR: {c}"),
+            };
+            self.messages.push(Message::Tool {
+                tool_name: name.cloned().unwrap_or_default(),
+                content,
+            });
+        }
+    }
+}
+
+impl Model for OllamaAgent {
+    fn turn(&mut self, results: Vec<ToolResult>) -> anyhow::Result<Vec<RawCall>> {
+        self.push_results(results);
+
+        let body = post(&self.http, &self.url, &self.request())?;
+        self.usage += usage_from_body(&body);
+        let message = body["message"].clone();
+        if message.is_null() {
+            anyhow::bail!("Ollama response missing a message: {body}");
+        }
+        let turn: AssistantTurn = serde_json::from_value(message)
+            .map_err(|e| anyhow::anyhow!("Ollama assistant message did not parse: {e}"))?;
+        let calls = raw_calls(&turn.tool_calls);
+        self.messages.push(Message::Assistant(turn));
+        Ok(calls)
+    }
+
+    fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    fn transcript(&self) -> anyhow::Result<Value> {
+        Ok(serde_json::to_value(&self.messages)?)
+    }
+
+    fn follow_up(&mut self, results: Vec<ToolResult>, transform: &str) -> anyhow::Result<()> {
+        self.push_results(results);
+        self.messages.push(Message::User {
+            content: transform.to_string(),
+        });
+        Ok(())
+    }
+}
+
+fn raw_calls(tool_calls: &[ToolCall]) -> Vec<RawCall> {
+    tool_calls
+        .iter()
+        .enumerate()
+        .map(|(i, c)| RawCall {
+            id: format!("call_{i}"),
+            name: c.function.name.clone(),
+            args: c.function.arguments.clone(),
+        })
+        .collect()
+}
+
+fn usage_from_body(body: &Value) -> Usage {
+    let count = |k: &str| body[k].as_u64().unwrap_or(0);
+    Usage {
+        input_tokens: count("prompt_eval_count"),
+        output_tokens: count("eval_count"),
+        ..Usage::default()
+    }
+}
+
+fn post(http: &Http, url: &str, req: &Request) -> anyhow::Result<Value> {
+    http.send_json(http.post(url).json(req))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn agent() -> OllamaAgent {
+        let tools = crate::agent::tools();
+        let seed = Seed {
+            system: "SYS",
+            selected: "selected",
+            transform: "transform",
+        };
+        OllamaAgent::new("qwen3".into(), &Config::default(), &seed, &tools)
+    }
+
+    fn request_json(agent: &OllamaAgent) -> Value {
+        serde_json::to_value(agent.request()).unwrap()
+    }
+
+    #[test]
+    fn agent_request_carries_tools_and_seed() {
+        let agent = agent();
+        assert_eq!(agent.url, "http://localhost:11434/api/chat");
+        let req = request_json(&agent);
+
+        assert_eq!(req["stream"], false);
+        assert_eq!(req["messages"][0]["role"], "system");
+        assert_eq!(req["messages"][0]["content"], "SYS");
+        assert_eq!(req["messages"][1]["role"], "user");
+        assert_eq!(req["messages"][1]["content"], "transform");
+        assert_eq!(req["messages"][2]["role"], "assistant");
+        let call = &req["messages"][2]["tool_calls"][0]["function"];
+        assert_eq!(call["name"], "view");
+        assert_eq!(call["arguments"], json!({}));
+        assert_eq!(req["messages"][3]["role"], "tool");
+        assert_eq!(req["messages"][3]["tool_name"], "view");
+        assert_eq!(req["messages"][3]["content"], "selected");
+        assert_eq!(req["tools"][0]["type"], "function");
+        let names: Vec<&str> = req["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["function"]["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "edit",
+                "edit_lines",
+                "regex_replace",
+                "view",
+                "reset",
+                "finish"
+            ]
+        );
+    }
+
+    #[test]
+    fn results_are_named_after_the_calls_they_answer() {
+        let mut agent = agent();
+        let turn: AssistantTurn = serde_json::from_value(json!({
+            "role": "assistant",
+            "content": "",
+            "tool_calls": [
+                { "function": { "name": "edit", "arguments": { "old": "a", "new": "b" } } },
+                { "function": { "name": "view", "arguments": {} } }
+            ]
+        }))
+        .unwrap();
+        let calls = raw_calls(&turn.tool_calls);
+        assert_eq!(calls[0].id, "call_0");
+        assert_eq!(calls[0].args["new"], "b");
+        agent.messages.push(Message::Assistant(turn));
+
+        agent.push_results(vec![
+            ToolResult {
+                id: "call_0".into(),
+                result: Err("nope".into()),
+            },
+            ToolResult {
+                id: "call_1".into(),
+                result: Ok("text".into()),
+            },
+        ]);
+        let req = request_json(&agent);
+        assert_eq!(req["messages"][5]["tool_name"], "edit");
+        assert_eq!(req["messages"][5]["content"], "ERROR: nope");
+        assert_eq!(req["messages"][6]["tool_name"], "view");
+        assert_eq!(req["messages"][6]["content"], "text");
+    }
+
+    #[test]
+    fn echoed_assistant_turn_is_verbatim() {
+        let mut agent = agent();
+        let raw = json!({
+            "content": "",
+            "thinking": "hmm",
+            "tool_calls": [
+                { "function": { "index": 0, "name": "finish", "arguments": {} } }
+            ]
+        });
+        let turn: AssistantTurn = serde_json::from_value(raw.clone()).unwrap();
+        agent.messages.push(Message::Assistant(turn));
+        let mut want = raw;
+        want["role"] = json!("assistant");
+        assert_eq!(request_json(&agent)["messages"][4], want);
+    }
+
+    #[test]
+    fn resumed_transcript_round_trips() {
+        let agent = agent();
+        let tools = crate::agent::tools();
+        let transcript = agent.transcript().unwrap();
+        let resumed =
+            OllamaAgent::resume("qwen3".into(), &Config::default(), transcript, &tools).unwrap();
+        assert_eq!(request_json(&resumed), request_json(&agent));
+    }
+
+    #[test]
+    fn usage_is_read_from_the_response() {
+        let body = json!({ "prompt_eval_count": 12, "eval_count": 3 });
+        assert_eq!(
+            usage_from_body(&body),
+            Usage {
+                input_tokens: 12,
+                output_tokens: 3,
+                ..Usage::default()
+            }
+        );
+    }
+}
@@ -2,11 +2,13 @@ use schemars::Schema;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 
-use crate::agent::{Model, RawCall, Seed, Tool, ToolResult, SEED_CALL_ID, SEED_TOOL};
+use crate::agent::{Model, RawCall, Seed, Tool, ToolResult, Usage, SEED_CALL_ID, SEED_TOOL};
+use crate::backend::{stream_ended_early, stream_error, ApiError, Http, SseEvent};
+use crate::config_files::Config;
 
-const API_URL: &str = "https://api.openai.com/v1/chat/completions";
+pub(crate) const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(tag = "role", rename_all = "snake_case")]
 enum Message {
     System {
@@ -75,9 +77,12 @@ struct FunctionDef {
 pub struct OpenaiAgent {
     key: String,
     model: String,
-    client: reqwest::blocking::Client,
+    url: String,
+    http: Http,
     messages: Vec<Message>,
     tools: Vec<ToolDef>,
+    reasoning_effort: Option<String>,
+    usage: Usage,
 }
 
 #[derive(Serialize)]
@@ -86,10 +91,14 @@ struct Request<'a> {
     messages: &'a [Message],
     tools: &'a [ToolDef],
     tool_choice: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_effort: Option<&'a str>,
+    stream: bool,
+    stream_options: Value,
 }
 
 impl OpenaiAgent {
-    pub fn new(key: String, model: String, seed: &Seed, tools: &[Tool]) -> Self {
+    pub fn new(key: String, model: String, config: &Config, seed: &Seed, tools: &[Tool]) -> Self {
         let messages = vec![
             Message::System {
                 content: seed.system.to_string(),
@@ -117,6 +126,30 @@ impl OpenaiAgent {
                 content: seed.selected.to_string(),
             },
         ];
+        Self::with_messages(key, model, config, messages, tools)
+    }
+
+    pub fn resume(
+        key: String,
+        model: String,
+        config: &Config,
+        transcript: Value,
+        tools: &[Tool],
+    ) -> anyhow::Result<Self> {
+        let messages = serde_json::from_value(transcript)
+            .map_err(|e| anyhow::anyhow!("saved OpenAI conversation did not parse: {e}"))?;
+        Ok(Self::with_messages(key, model, config, messages, tools))
+    }
+
+    fn with_messages(
+        key: String,
+        model: String,
+        config: &Config,
+        messages: Vec<Message>,
+        tools: &[Tool],
+    ) -> Self {
+        let base_url = config.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL);
+        let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
         let tools = tools
             .iter()
             .map(|t| ToolDef {
@@ -131,9 +164,12 @@ impl OpenaiAgent {
         OpenaiAgent {
             key,
             model,
-            client: crate::backend::http_client(),
+            url,
+            http: Http::new(config),
             messages,
             tools,
+            reasoning_effort: config.reasoning_effort.clone(),
+            usage: Usage::default(),
         }
     }
 
@@ -143,12 +179,15 @@ impl OpenaiAgent {
             messages: &self.messages,
             tools: &self.tools,
             tool_choice: "auto",
+            reasoning_effort: self.reasoning_effort.as_deref(),
+            stream: true,
+            stream_options: serde_json::json!({ "include_usage": true }),
         }
     }
 }
 
-impl Model for OpenaiAgent {
-    fn turn(&mut self, results: Vec<ToolResult>) -> anyhow::Result<Vec<RawCall>> {
+impl OpenaiAgent {
+    fn push_results(&mut self, results: Vec<ToolResult>) {
         for r in results {
             let content = match r.result {
                 Ok(c) => c,
@@ -159,8 +198,16 @@ impl Model for OpenaiAgent {
                 content,
             });
         }
+    }
+}
+
+impl Model for OpenaiAgent {
+    fn turn(&mut self, results: Vec<ToolResult>) -> anyhow::Result<Vec<RawCall>> {
+        self.push_results(results);
 
-        let body = post(&self.client, &self.key, &self.request())?;
+        let request = post(&self.http, &self.url, &self.key, &self.request());
+        let body = self.http.stream_sse::<Stream>(request)?;
+        self.usage += usage_from_body(&body);
         let message = body["choices"][0]["message"].clone();
         if message.is_null() {
             anyhow::bail!("OpenAI response missing a message: {body}");
@@ -171,6 +218,31 @@ impl Model for OpenaiAgent {
         self.messages.push(Message::Assistant(turn));
         Ok(calls)
     }
+
+    fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    fn transcript(&self) -> anyhow::Result<Value> {
+        Ok(serde_json::to_value(&self.messages)?)
+    }
+
+    fn follow_up(&mut self, results: Vec<ToolResult>, transform: &str) -> anyhow::Result<()> {
+        self.push_results(results);
+        self.messages.push(Message::User {
+            content: transform.to_string(),
+        });
+        Ok(())
+    }
+}
+
+fn usage_from_body(body: &Value) -> Usage {
+    let count = |k: &str| body["usage"][k].as_u64().unwrap_or(0);
+    Usage {
+        input_tokens: count("prompt_tokens"),
+        output_tokens: count("completion_tokens"),
+        ..Usage::default()
+    }
 }
 
 fn raw_calls(tool_calls: &[ToolCall]) -> Vec<RawCall> {
@@ -179,14 +251,127 @@ fn raw_calls(tool_calls: &[ToolCall]) -> Vec<RawCall> {
         .map(|c| RawCall {
             id: c.id.clone(),
             name: c.function.name.clone(),
-            args: serde_json::from_str(&c.function.arguments)
-                .unwrap_or_else(|_| serde_json::json!({})),
+            args: crate::agent::arguments(&c.function.arguments),
         })
         .collect()
 }
 
-fn post(client: &reqwest::blocking::Client, key: &str, req: &Request) -> anyhow::Result<Value> {
-    crate::backend::send_json(client.post(API_URL).bearer_auth(key).json(req))
+pub(crate) fn post(
+    http: &Http,
+    url: &str,
+    key: &str,
+    req: &impl Serialize,
+) -> reqwest::blocking::RequestBuilder {
+    let request = http.post(url).json(req);
+    if key.is_empty() {
+        return request;
+    }
+    request.bearer_auth(key)
+}
+
+#[derive(Default)]
+struct Stream {
+    message: Map<String, Value>,
+    tool_calls: Vec<Value>,
+    usage: Value,
+    done: bool,
+}
+
+impl crate::backend::Stream for Stream {
+    fn event(&mut self, event: SseEvent) -> Result<(), ApiError> {
+        if event.data == "[DONE]" {
+            self.done = true;
+            return Ok(());
+        }
+        let Ok(chunk) = serde_json::from_str::<Value>(&event.data) else {
+            return Ok(());
+        };
+        if let Some(error) = chunk.get("error") {
+            return Err(stream_error(error));
+        }
+        if !chunk["usage"].is_null() {
+            self.usage = chunk["usage"].clone();
+        }
+        let choice = &chunk["choices"][0];
+        if !choice["finish_reason"].is_null() {
+            self.done = true;
+        }
+        let Some(delta) = choice["delta"].as_object() else {
+            return Ok(());
+        };
+        for (key, value) in delta {
+            match (key.as_str(), value) {
+                ("role", _) | (_, Value::Null) => {}
+                ("tool_calls", Value::Array(calls)) => {
+                    for call in calls {
+                        self.tool_call(call);
+                    }
+                }
+                (_, Value::String(piece)) => append(&mut self.message, key, piece),
+                _ => {
+                    self.message.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Value, ApiError> {
+        if !self.done {
+            return Err(stream_ended_early());
+        }
+        self.message.insert("role".into(), "assistant".into());
+        self.message.entry("content").or_insert(Value::Null);
+        if !self.tool_calls.is_empty() {
+            self.message
+                .insert("tool_calls".into(), Value::Array(self.tool_calls));
+        }
+        Ok(serde_json::json!({
+            "choices": [{ "message": self.message }],
+            "usage": self.usage,
+        }))
+    }
+}
+
+impl Stream {
+    // Tool calls arrive as fragments keyed by `index`: the id and name once, the arguments
+    // string a piece at a time.
+    fn tool_call(&mut self, fragment: &Value) {
+        let index = fragment["index"].as_u64().unwrap_or(0) as usize;
+        if self.tool_calls.len() <= index {
+            self.tool_calls.resize_with(index + 1, || {
+                serde_json::json!({
+                    "id": "",
+                    "type": "function",
+                    "function": { "name": "", "arguments": "" },
+                })
+            });
+        }
+        let Value::Object(call) = &mut self.tool_calls[index] else {
+            return;
+        };
+        if let Some(id) = fragment["id"].as_str() {
+            call.insert("id".into(), id.into());
+        }
+        let Value::Object(function) = &mut call["function"] else {
+            return;
+        };
+        if let Some(name) = fragment["function"]["name"].as_str() {
+            append(function, "name", name);
+            crate::progress::tool(name);
+        }
+        if let Some(arguments) = fragment["function"]["arguments"].as_str() {
+            append(function, "arguments", arguments);
+        }
+    }
+}
+
+fn append(map: &mut Map<String, Value>, key: &str, piece: &str) {
+    let text = format!(
+        "{}{piece}",
+        map.get(key).and_then(Value::as_str).unwrap_or_default()
+    );
+    map.insert(key.to_string(), Value::String(text));
 }
 
 #[cfg(test)]
@@ -206,7 +391,13 @@ mod tests {
             selected: "selected",
             transform: "transform",
         };
-        let agent = OpenaiAgent::new("k".into(), "gpt-5.5".into(), &seed, &tools);
+        let agent = OpenaiAgent::new(
+            "k".into(),
+            "gpt-5.5".into(),
+            &Config::default(),
+            &seed,
+            &tools,
+        );
         let req = request_json(&agent);
 
         assert_eq!(req["tool_choice"], "auto");
@@ -227,7 +418,53 @@ mod tests {
             .iter()
             .map(|t| t["function"]["name"].as_str().unwrap())
             .collect();
-        assert_eq!(names, ["edit", "view", "reset", "finish"]);
+        assert_eq!(
+            names,
+            [
+                "edit",
+                "edit_lines",
+                "regex_replace",
+                "view",
+                "reset",
+                "finish"
+            ]
+        );
+    }
+
+    #[test]
+    fn base_url_is_configurable() {
+        let tools = crate::agent::tools();
+        let seed = Seed {
+            system: "SYS",
+            selected: "selected",
+            transform: "transform",
+        };
+        let agent = OpenaiAgent::new("k".into(), "m".into(), &Config::default(), &seed, &tools);
+        assert_eq!(agent.url, "https://api.openai.com/v1/chat/completions");
+        let config = Config {
+            base_url: Some("http://localhost:8080/v1/".into()),
+            ..Config::default()
+        };
+        let agent = OpenaiAgent::new(String::new(), "m".into(), &config, &seed, &tools);
+        assert_eq!(agent.url, "http://localhost:8080/v1/chat/completions");
+    }
+
+    #[test]
+    fn reasoning_effort_is_sent_only_when_configured() {
+        let tools = crate::agent::tools();
+        let seed = Seed {
+            system: "SYS",
+            selected: "selected",
+            transform: "transform",
+        };
+        let agent = OpenaiAgent::new("k".into(), "m".into(), &Config::default(), &seed, &tools);
+        assert!(request_json(&agent).get("reasoning_effort").is_none());
+        let config = Config {
+            reasoning_effort: Some("high".into()),
+            ..Config::default()
+        };
+        let agent = OpenaiAgent::new("k".into(), "m".into(), &config, &seed, &tools);
+        assert_eq!(request_json(&agent)["reasoning_effort"], "high");
     }
 
     #[test]
@@ -238,7 +475,7 @@ mod tests {
             selected: "selected",
             transform: "transform",
         };
-        let mut agent = OpenaiAgent::new("k".into(), "m".into(), &seed, &tools);
+        let mut agent = OpenaiAgent::new("k".into(), "m".into(), &Config::default(), &seed, &tools);
         agent.messages.push(Message::Tool {
             tool_call_id: "c1".into(),
             content: "ok".into(),
@@ -258,7 +495,7 @@ mod tests {
             selected: "selected",
             transform: "transform",
         };
-        let mut agent = OpenaiAgent::new("k".into(), "m".into(), &seed, &tools);
+        let mut agent = OpenaiAgent::new("k".into(), "m".into(), &Config::default(), &seed, &tools);
         let raw = json!({
             "role": "assistant",
             "content": null,
@@ -328,6 +565,51 @@ mod tests {
         assert!(wire.get("tool_calls").is_none());
     }
 
+    #[test]
+    fn resumed_transcript_round_trips_and_takes_a_follow_up() {
+        let tools = crate::agent::tools();
+        let seed = Seed {
+            system: "SYS",
+            selected: "selected",
+            transform: "transform",
+        };
+        let mut agent = OpenaiAgent::new("k".into(), "m".into(), &Config::default(), &seed, &tools);
+        let turn: AssistantTurn = serde_json::from_value(json!({
+            "role": "assistant",
+            "content": null,
+            "reasoning": "hmm",
+            "tool_calls": [
+                { "id": "c1", "type": "function",
+                  "function": { "name": "finish", "arguments": "{}" } }
+            ]
+        }))
+        .unwrap();
+        agent.messages.push(Message::Assistant(turn));
+        let before = request_json(&agent);
+
+        let transcript = agent.transcript().unwrap();
+        let mut resumed = OpenaiAgent::resume(
+            "k".into(),
+            "m".into(),
+            &Config::default(),
+            transcript,
+            &tools,
+        )
+        .unwrap();
+        assert_eq!(request_json(&resumed), before);
+
+        let results = vec![ToolResult {
+            id: "c1".into(),
+            result: Ok("finished".into()),
+        }];
+        resumed.follow_up(results, "more").unwrap();
+        let req = request_json(&resumed);
+        assert_eq!(req["messages"][5]["role"], "tool");
+        assert_eq!(req["messages"][5]["tool_call_id"], "c1");
+        assert_eq!(req["messages"][6]["role"], "user");
+        assert_eq!(req["messages"][6]["content"], "more");
+    }
+
     #[test]
     fn parses_tool_calls_with_string_arguments() {
         let raw = json!({
@@ -348,10 +630,93 @@ mod tests {
         assert_eq!(calls[1].name, "finish");
     }
 
+    #[test]
+    fn usage_is_read_from_the_response() {
+        let body = json!({ "usage": { "prompt_tokens": 12, "completion_tokens": 3 } });
+        assert_eq!(
+            usage_from_body(&body),
+            Usage {
+                input_tokens: 12,
+                output_tokens: 3,
+                ..Usage::default()
+            }
+        );
+    }
+
     #[test]
     fn no_tool_calls_is_no_calls() {
         let raw = json!({ "role": "assistant", "content": "done" });
         let turn: AssistantTurn = serde_json::from_value(raw).unwrap();
         assert!(raw_calls(turn.tool_calls.as_deref().unwrap_or(&[])).is_empty());
     }
+
+    #[test]
+    fn streamed_chunks_rebuild_the_response_body() {
+        use crate::backend::Stream as _;
+
+        let chunks = [
+            json!({ "choices": [{ "delta": { "role": "assistant", "content": null, "reasoning": "hm" } }] }),
+            json!({ "choices": [{ "delta": { "reasoning": "m" } }] }),
+            json!({ "choices": [{ "delta": { "tool_calls": [
+                { "index": 0, "id": "c1", "type": "function",
+                  "function": { "name": "edit", "arguments": "" } }
+            ] } }] }),
+            json!({ "choices": [{ "delta": { "tool_calls": [
+                { "index": 0, "function": { "arguments": "{\"old\":\"a\"," } }
+            ] } }] }),
+            json!({ "choices": [{ "delta": { "tool_calls": [
+                { "index": 0, "function": { "arguments": "\"new\":\"b\"}" } },
+                { "index": 1, "id": "c2", "type": "function",
+                  "function": { "name": "finish", "arguments": "{}" } }
+            ] } }] }),
+            json!({ "choices": [{ "delta": {}, "finish_reason": "tool_calls" }] }),
+            json!({ "choices": [], "usage": { "prompt_tokens": 12, "completion_tokens": 3 } }),
+        ];
+        let mut stream = Stream::default();
+        for chunk in &chunks {
+            let event = SseEvent {
+                event: String::new(),
+                data: chunk.to_string(),
+            };
+            stream.event(event).unwrap();
+        }
+        let done = SseEvent {
+            event: String::new(),
+            data: "[DONE]".into(),
+        };
+        stream.event(done).unwrap();
+
+        let body = stream.finish().unwrap();
+        let turn: AssistantTurn =
+            serde_json::from_value(body["choices"][0]["message"].clone()).unwrap();
+        assert_eq!(turn.extra["reasoning"], "hmm");
+        let calls = raw_calls(turn.tool_calls.as_deref().unwrap_or(&[]));
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "c1");
+        assert_eq!(calls[0].args, json!({ "old": "a", "new": "b" }));
+        assert_eq!(calls[1].name, "finish");
+        assert_eq!(
+            usage_from_body(&body),
+            Usage {
+                input_tokens: 12,
+                output_tokens: 3,
+                ..Usage::default()
+            }
+        );
+    }
+
+    #[test]
+    fn truncated_stream_is_an_error() {
+        use crate::backend::Stream as _;
+
+        let mut stream = Stream::default();
+        let chunk = json!({ "choices": [{ "delta": { "content": "par" } }] });
+        stream
+            .event(SseEvent {
+                event: String::new(),
+                data: chunk.to_string(),
+            })
+            .unwrap();
+        assert!(stream.finish().is_err());
+    }
 }
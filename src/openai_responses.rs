@@ -0,0 +1,565 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::agent::{Model, RawCall, Seed, Tool, ToolResult, Usage, SEED_CALL_ID, SEED_TOOL};
+use crate::backend::{stream_ended_early, stream_error, ApiError, Http, SseEvent};
+use crate::config_files::Config;
+use crate::openai::{post, DEFAULT_BASE_URL};
+
+// Reasoning items, output messages and anything else we don't act on are kept as-is so
+// they can be replayed to the API exactly as it sent them.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Item {
+    Message {
+        role: String,
+        content: Value,
+        #[serde(flatten)]
+        extra: Map<String, Value>,
+    },
+    FunctionCall {
+        call_id: String,
+        name: String,
+        arguments: String,
+        #[serde(flatten)]
+        extra: Map<String, Value>,
+    },
+    FunctionCallOutput {
+        call_id: String,
+        output: String,
+    },
+    #[serde(untagged)]
+    Other(Value),
+}
+
+// The whole conversation is kept so a session can be saved and resumed, but only the
+// items after `sent` are new to the server; the rest are reached through
+// `previous_response_id`.
+#[derive(Serialize, Deserialize)]
+struct Conversation {
+    items: Vec<Item>,
+    #[serde(default)]
+    previous_response_id: Option<String>,
+    #[serde(default)]
+    sent: usize,
+}
+
+#[derive(Serialize)]
+struct ToolDef {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    name: String,
+    description: String,
+    parameters: schemars::Schema,
+    strict: bool,
+}
+
+pub struct ResponsesAgent {
+    key: String,
+    model: String,
+    system: String,
+    url: String,
+    http: Http,
+    conversation: Conversation,
+    tools: Vec<ToolDef>,
+    reasoning_effort: Option<String>,
+    usage: Usage,
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    model: &'a str,
+    instructions: &'a str,
+    input: &'a [Item],
+    tools: &'a [ToolDef],
+    tool_choice: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_response_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning: Option<Value>,
+    stream: bool,
+}
+
+impl ResponsesAgent {
+    pub fn new(key: String, model: String, config: &Config, seed: &Seed, tools: &[Tool]) -> Self {
+        let items = vec![
+            Item::Message {
+                role: "user".to_string(),
+                content: json!(seed.transform),
+                extra: Map::new(),
+            },
+            Item::FunctionCall {
+                call_id: SEED_CALL_ID.to_string(),
+                name: SEED_TOOL.to_string(),
+                arguments: Seed::seed_call_args().to_string(),
+                extra: Map::new(),
+            },
+            Item::FunctionCallOutput {
+                call_id: SEED_CALL_ID.to_string(),
+                output: seed.selected.to_string(),
+            },
+        ];
+        let conversation = Conversation {
+            items,
+            previous_response_id: None,
+            sent: 0,
+        };
+        Self::with_conversation(key, model, config, seed.system, conversation, tools)
+    }
+
+    pub fn resume(
+        key: String,
+        model: String,
+        config: &Config,
+        system: &str,
+        transcript: Value,
+        tools: &[Tool],
+    ) -> anyhow::Result<Self> {
+        let conversation = serde_json::from_value(transcript).map_err(|e| {
+            anyhow::anyhow!("saved OpenAI Responses conversation did not parse: {e}")
+        })?;
+        Ok(Self::with_conversation(
+            key,
+            model,
+            config,
+            system,
+            conversation,
+            tools,
+        ))
+    }
+
+    fn with_conversation(
+        key: String,
+        model: String,
+        config: &Config,
+        system: &str,
+        conversation: Conversation,
+        tools: &[Tool],
+    ) -> Self {
+        let base_url = config.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL);
+        let url = format!("{}/responses", base_url.trim_end_matches('/'));
+        let tools = tools
+            .iter()
+            .map(|t| ToolDef {
+                kind: "function",
+                name: t.name.to_string(),
+                description: t.description.to_string(),
+                parameters: t.input_schema.clone(),
+                strict: false,
+            })
+            .collect();
+        ResponsesAgent {
+            key,
+            model,
+            system: system.to_string(),
+            url,
+            http: Http::new(config),
+            conversation,
+            tools,
+            reasoning_effort: config.reasoning_effort.clone(),
+            usage: Usage::default(),
+        }
+    }
+
+    fn request(&self) -> Request<'_> {
+        let c = &self.conversation;
+        let input = match c.previous_response_id {
+            Some(_) => &c.items[c.sent..],
+            None => &c.items[..],
+        };
+        Request {
+            model: &self.model,
+            instructions: &self.system,
+            input,
+            tools: &self.tools,
+            tool_choice: "auto",
+            previous_response_id: c.previous_response_id.as_deref(),
+            reasoning: self
+                .reasoning_effort
+                .as_ref()
+                .map(|effort| json!({ "effort": effort })),
+            stream: true,
+        }
+    }
+
+    fn record(&mut self, body: &Value) -> anyhow::Result<Vec<RawCall>> {
+        self.usage += usage_from_body(body);
+        let (Some(id), Some(output)) = (body["id"].as_str(), body["output"].as_array()) else {
+            anyhow::bail!("OpenAI response missing id or output: {body}");
+        };
+        let output: Vec<Item> = serde_json::from_value(Value::Array(output.clone()))
+            .map_err(|e| anyhow::anyhow!("OpenAI response output did not parse: {e}"))?;
+        let calls = raw_calls(&output);
+        let c = &mut self.conversation;
+        c.previous_response_id = Some(id.to_string());
+        c.items.extend(output);
+        c.sent = c.items.len();
+        Ok(calls)
+    }
+
+    fn push_results(&mut self, results: Vec<ToolResult>) {
+        for r in results {
+            let output = match r.result {
+                Ok(c) => c,
+                Err(c) => format!("ERROR: {c}"),
+            };
+            self.conversation.items.push(Item::FunctionCallOutput {
+                call_id: r.id,
+                output,
+            });
+        }
+    }
+}
+
+impl Model for ResponsesAgent {
+    fn turn(&mut self, results: Vec<ToolResult>) -> anyhow::Result<Vec<RawCall>> {
+        self.push_results(results);
+
+        let request = post(&self.http, &self.url, &self.key, &self.request());
+        let body = self.http.stream_sse::<Stream>(request)?;
+        self.record(&body)
+    }
+
+    fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    fn transcript(&self) -> anyhow::Result<Value> {
+        Ok(serde_json::to_value(&self.conversation)?)
+    }
+
+    fn follow_up(&mut self, results: Vec<ToolResult>, transform: &str) -> anyhow::Result<()> {
+        self.push_results(results);
+        self.conversation.items.push(Item::Message {
+            role: "user".to_string(),
+            content: json!(transform),
+            extra: Map::new(),
+        });
+        Ok(())
+    }
+}
+
+fn usage_from_body(body: &Value) -> Usage {
+    let count = |k: &str| body["usage"][k].as_u64().unwrap_or(0);
+    Usage {
+        input_tokens: count("input_tokens"),
+        output_tokens: count("output_tokens"),
+        ..Usage::default()
+    }
+}
+
+fn raw_calls(output: &[Item]) -> Vec<RawCall> {
+    output
+        .iter()
+        .filter_map(|item| match item {
+            Item::FunctionCall {
+                call_id,
+                name,
+                arguments,
+                ..
+            } => Some(RawCall {
+                id: call_id.clone(),
+                name: name.clone(),
+                args: crate::agent::arguments(arguments),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+// Output items are built up from their deltas, then replaced by the finished item once
+// it's done; `response.completed` carries the id and usage.
+#[derive(Default)]
+struct Stream {
+    output: Vec<Value>,
+    response: Option<Value>,
+}
+
+impl crate::backend::Stream for Stream {
+    fn event(&mut self, event: SseEvent) -> Result<(), ApiError> {
+        let Ok(data) = serde_json::from_str::<Value>(&event.data) else {
+            return Ok(());
+        };
+        let index = data["output_index"].as_u64().unwrap_or(0) as usize;
+        let kind = data["type"].as_str().unwrap_or(&event.event);
+        match kind {
+            "response.output_item.added" | "response.output_item.done" => {
+                let item = data["item"].clone();
+                if kind.ends_with("added") && item["type"] == "function_call" {
+                    crate::progress::tool(item["name"].as_str().unwrap_or_default());
+                }
+                if self.output.len() <= index {
+                    self.output.resize(index + 1, Value::Null);
+                }
+                self.output[index] = item;
+            }
+            "response.output_text.delta" => {
+                let Some(Value::Array(content)) = self
+                    .output
+                    .get_mut(index)
+                    .and_then(|item| item.get_mut("content"))
+                else {
+                    return Ok(());
+                };
+                let part = data["content_index"].as_u64().unwrap_or(0) as usize;
+                if content.len() <= part {
+                    content.resize(part + 1, json!({ "type": "output_text", "text": "" }));
+                }
+                append(&mut content[part], "text", &data["delta"]);
+            }
+            "response.function_call_arguments.delta" => {
+                if let Some(item) = self.output.get_mut(index) {
+                    append(item, "arguments", &data["delta"]);
+                }
+            }
+            "response.completed" | "response.incomplete" => {
+                self.response = Some(data["response"].clone());
+            }
+            "response.failed" => return Err(stream_error(&data["response"]["error"])),
+            "error" => return Err(stream_error(&data)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, ApiError> {
+        let Some(mut response) = self.response else {
+            return Err(stream_ended_early());
+        };
+        if !self.output.is_empty() {
+            response["output"] = Value::Array(self.output);
+        }
+        Ok(response)
+    }
+}
+
+fn append(item: &mut Value, field: &str, piece: &Value) {
+    let piece = piece.as_str().unwrap_or_default();
+    let text = format!("{}{piece}", item[field].as_str().unwrap_or_default());
+    item[field] = Value::String(text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent() -> ResponsesAgent {
+        let tools = crate::agent::tools();
+        let seed = Seed {
+            system: "SYS",
+            selected: "selected",
+            transform: "transform",
+        };
+        ResponsesAgent::new(
+            "k".into(),
+            "gpt-5.5".into(),
+            &Config::default(),
+            &seed,
+            &tools,
+        )
+    }
+
+    fn request_json(agent: &ResponsesAgent) -> Value {
+        serde_json::to_value(agent.request()).unwrap()
+    }
+
+    fn response() -> Value {
+        json!({
+            "id": "resp_1",
+            "output": [
+                { "type": "reasoning", "id": "rs_1", "summary": [],
+                  "encrypted_content": "opaque" },
+                { "type": "function_call", "id": "fc_1", "call_id": "c1", "name": "edit",
+                  "arguments": "{\"old\":\"a\",\"new\":\"b\"}", "status": "completed" }
+            ],
+            "usage": { "input_tokens": 20, "output_tokens": 5 }
+        })
+    }
+
+    #[test]
+    fn first_request_sends_the_seeded_conversation() {
+        let agent = agent();
+        assert_eq!(agent.url, "https://api.openai.com/v1/responses");
+        let req = request_json(&agent);
+
+        assert_eq!(req["instructions"], "SYS");
+        assert!(req.get("reasoning").is_none());
+        assert_eq!(req["tool_choice"], "auto");
+        assert_eq!(req["stream"], true);
+        assert!(req.get("previous_response_id").is_none());
+        assert_eq!(
+            req["input"][0],
+            json!({ "type": "message", "role": "user", "content": "transform" })
+        );
+        assert_eq!(req["input"][1]["type"], "function_call");
+        assert_eq!(req["input"][1]["name"], "view");
+        assert_eq!(
+            req["input"][2],
+            json!({ "type": "function_call_output", "call_id": "seed_view", "output": "selected" })
+        );
+        assert_eq!(req["tools"][0]["type"], "function");
+        let names: Vec<&str> = req["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "edit",
+                "edit_lines",
+                "regex_replace",
+                "view",
+                "reset",
+                "finish"
+            ]
+        );
+    }
+
+    #[test]
+    fn reasoning_effort_is_configurable() {
+        let tools = crate::agent::tools();
+        let seed = Seed {
+            system: "SYS",
+            selected: "selected",
+            transform: "transform",
+        };
+        let config = Config {
+            reasoning_effort: Some("low".into()),
+            ..Config::default()
+        };
+        let agent = ResponsesAgent::new("k".into(), "m".into(), &config, &seed, &tools);
+        assert_eq!(
+            request_json(&agent)["reasoning"],
+            json!({ "effort": "low" })
+        );
+    }
+
+    #[test]
+    fn later_turns_chain_on_the_previous_response() {
+        let mut agent = agent();
+        let calls = agent.record(&response()).unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "c1");
+        assert_eq!(calls[0].args["new"], "b");
+
+        agent.push_results(vec![ToolResult {
+            id: "c1".into(),
+            result: Err("no match".into()),
+        }]);
+        let req = request_json(&agent);
+        assert_eq!(req["previous_response_id"], "resp_1");
+        assert_eq!(
+            req["input"],
+            json!([{ "type": "function_call_output", "call_id": "c1", "output": "ERROR: no match" }])
+        );
+    }
+
+    #[test]
+    fn transcript_keeps_reasoning_items_verbatim() {
+        let mut agent = agent();
+        agent.record(&response()).unwrap();
+        let transcript = agent.transcript().unwrap();
+        assert_eq!(transcript["items"][3], response()["output"][0]);
+        assert_eq!(transcript["items"][4], response()["output"][1]);
+
+        let tools = crate::agent::tools();
+        let mut resumed = ResponsesAgent::resume(
+            "k".into(),
+            "gpt-5.5".into(),
+            &Config::default(),
+            "SYS",
+            transcript,
+            &tools,
+        )
+        .unwrap();
+        assert_eq!(request_json(&resumed), request_json(&agent));
+
+        let results = vec![ToolResult {
+            id: "c1".into(),
+            result: Ok("ok".into()),
+        }];
+        resumed.follow_up(results, "more").unwrap();
+        let req = request_json(&resumed);
+        assert_eq!(req["previous_response_id"], "resp_1");
+        assert_eq!(req["input"][0]["call_id"], "c1");
+        assert_eq!(req["input"][1]["content"], "more");
+    }
+
+    #[test]
+    fn streamed_events_rebuild_the_response_body() {
+        use crate::backend::Stream as _;
+
+        let call = json!({ "type": "function_call", "id": "fc_1", "call_id": "c1",
+                           "name": "edit", "arguments": "" });
+        let events = [
+            json!({ "type": "response.created", "response": { "id": "resp_1" } }),
+            json!({ "type": "response.output_item.added", "output_index": 0,
+                    "item": response()["output"][0] }),
+            json!({ "type": "response.output_item.added", "output_index": 1, "item": call }),
+            json!({ "type": "response.function_call_arguments.delta", "output_index": 1,
+                    "delta": "{\"old\":\"a\"," }),
+            json!({ "type": "response.function_call_arguments.delta", "output_index": 1,
+                    "delta": "\"new\":\"b\"}" }),
+            json!({ "type": "response.output_item.added", "output_index": 2,
+                    "item": { "type": "message", "role": "assistant", "content": [] } }),
+            json!({ "type": "response.output_text.delta", "output_index": 2,
+                    "content_index": 0, "delta": "on " }),
+            json!({ "type": "response.output_text.delta", "output_index": 2,
+                    "content_index": 0, "delta": "it" }),
+            json!({ "type": "response.completed", "response": {
+                "id": "resp_1", "output": [], "usage": response()["usage"] } }),
+        ];
+        let mut stream = Stream::default();
+        for data in &events {
+            stream
+                .event(SseEvent {
+                    event: data["type"].as_str().unwrap().into(),
+                    data: data.to_string(),
+                })
+                .unwrap();
+        }
+        let body = stream.finish().unwrap();
+        assert_eq!(body["output"][2]["content"][0]["text"], "on it");
+
+        let mut agent = agent();
+        let calls = agent.record(&body).unwrap();
+        assert_eq!(calls[0].args, json!({ "old": "a", "new": "b" }));
+        assert_eq!(agent.usage().input_tokens, 20);
+    }
+
+    #[test]
+    fn truncated_or_failed_streams_are_errors() {
+        use crate::backend::Stream as _;
+
+        let event = |data: Value| SseEvent {
+            event: String::new(),
+            data: data.to_string(),
+        };
+        let mut stream = Stream::default();
+        let added = json!({ "type": "response.output_item.added", "output_index": 0,
+                            "item": response()["output"][1] });
+        stream.event(event(added)).unwrap();
+        assert!(stream.finish().is_err());
+
+        let failed = json!({ "type": "response.failed",
+                             "response": { "error": { "code": "server_error" } } });
+        assert!(Stream::default().event(event(failed)).is_err());
+    }
+
+    #[test]
+    fn usage_is_read_from_the_response() {
+        let mut agent = agent();
+        agent.record(&response()).unwrap();
+        assert_eq!(
+            agent.usage(),
+            Usage {
+                input_tokens: 20,
+                output_tokens: 5,
+                ..Usage::default()
+            }
+        );
+        assert!(agent.record(&json!({ "error": "nope" })).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use std::io::{IsTerminal, Write};
+use std::sync::{Mutex, OnceLock};
+
+// A single status line on stderr, redrawn in place. Only shown when stderr is a
+// terminal so editor integrations and pipes never see it.
+
+struct State {
+    turn: usize,
+    tool: Option<String>,
+    received: usize,
+    edits: usize,
+    shown: bool,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    turn: 0,
+    tool: None,
+    received: 0,
+    edits: 0,
+    shown: false,
+});
+
+fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| std::io::stderr().is_terminal())
+}
+
+fn update(f: impl FnOnce(&mut State)) {
+    if !enabled() {
+        return;
+    }
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut state);
+    state.shown = true;
+    let mut stderr = std::io::stderr().lock();
+    let _ = write!(stderr, "\r\x1b[2K{}", render(&state));
+    let _ = stderr.flush();
+}
+
+pub fn turn(turn: usize) {
+    update(|s| {
+        s.turn = turn;
+        s.tool = None;
+        s.received = 0;
+    });
+}
+
+pub fn tool(name: &str) {
+    update(|s| s.tool = Some(name.to_string()));
+}
+
+pub fn received(bytes: usize) {
+    update(|s| s.received += bytes);
+}
+
+pub fn edits(applied: usize) {
+    update(|s| s.edits = applied);
+}
+
+pub fn clear() {
+    if !enabled() {
+        return;
+    }
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if std::mem::take(&mut state.shown) {
+        let _ = write!(std::io::stderr(), "\r\x1b[2K");
+    }
+}
+
+fn render(state: &State) -> String {
+    let activity = match (&state.tool, state.received) {
+        (Some(tool), _) => format!("calling {tool}"),
+        (None, 0) => "waiting for the model".to_string(),
+        (None, n) => format!("receiving ({n} bytes)"),
+    };
+    let plural = if state.edits == 1 { "" } else { "s" };
+    format!(
+        "refac: turn {}, {activity}, {} edit{plural} applied",
+        state.turn, state.edits
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_line_reports_turn_activity_and_edits() {
+        let mut state = State {
+            turn: 2,
+            tool: None,
+            received: 0,
+            edits: 1,
+            shown: false,
+        };
+        assert_eq!(
+            render(&state),
+            "refac: turn 2, waiting for the model, 1 edit applied"
+        );
+        state.received = 512;
+        state.edits = 3;
+        assert_eq!(
+            render(&state),
+            "refac: turn 2, receiving (512 bytes), 3 edits applied"
+        );
+        state.tool = Some("edit".into());
+        assert_eq!(
+            render(&state),
+            "refac: turn 2, calling edit, 3 edits applied"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Result;
+use similar::{ChangeTag, TextDiff};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Segment {
+    Same(String),
+    Changed { old: String, new: String },
+}
+
+pub fn segments(old: &str, new: &str) -> Vec<Segment> {
+    let mut out = Vec::new();
+    for change in TextDiff::from_lines(old, new).iter_all_changes() {
+        let line = change.value();
+        match (change.tag(), out.last_mut()) {
+            (ChangeTag::Equal, Some(Segment::Same(s))) => s.push_str(line),
+            (ChangeTag::Equal, _) => out.push(Segment::Same(line.to_string())),
+            (tag, Some(Segment::Changed { old, new })) => match tag {
+                ChangeTag::Delete => old.push_str(line),
+                _ => new.push_str(line),
+            },
+            (tag, _) => {
+                let (old, new) = match tag {
+                    ChangeTag::Delete => (line.to_string(), String::new()),
+                    _ => (String::new(), line.to_string()),
+                };
+                out.push(Segment::Changed { old, new });
+            }
+        }
+    }
+    out
+}
+
+pub enum Decision {
+    Accept,
+    Reject,
+    FollowUp(String),
+}
+
+pub fn review(
+    original: &str,
+    output: &str,
+    mut decide: impl FnMut(usize, usize, &str, &str) -> Result<Decision>,
+    mut follow_up: impl FnMut(&str, String) -> Result<String>,
+) -> Result<String> {
+    let segments = segments(original, output);
+    let total = segments
+        .iter()
+        .filter(|s| matches!(s, Segment::Changed { .. }))
+        .count();
+    let mut index = 0;
+    let mut out = String::with_capacity(output.len());
+    for segment in segments {
+        match segment {
+            Segment::Same(s) => out.push_str(&s),
+            Segment::Changed { old, mut new } => {
+                index += 1;
+                let kept = loop {
+                    match decide(index, total, &old, &new)? {
+                        Decision::Accept => break new,
+                        Decision::Reject => break old,
+                        Decision::FollowUp(transform) => new = follow_up(&new, transform)?,
+                    }
+                };
+                out.push_str(&kept);
+            }
+        }
+    }
+    Ok(out)
+}
+
+pub fn ask(index: usize, total: usize, old: &str, new: &str) -> Result<Decision> {
+    eprintln!("hunk {index}/{total}");
+    for line in old.lines() {
+        eprintln!("-{line}");
+    }
+    for line in new.lines() {
+        eprintln!("+{line}");
+    }
+    let choice = dialoguer::Select::new()
+        .with_prompt("Keep this change?")
+        .items(&["accept", "reject", "follow up with another transform"])
+        .default(0)
+        .interact()?;
+    Ok(match choice {
+        0 => Decision::Accept,
+        1 => Decision::Reject,
+        _ => Decision::FollowUp(
+            dialoguer::Input::new()
+                .with_prompt("Transform")
+                .interact_text()?,
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scripted(
+        decisions: Vec<Decision>,
+    ) -> impl FnMut(usize, usize, &str, &str) -> Result<Decision> {
+        let mut decisions = decisions.into_iter();
+        move |_, _, _, _| Ok(decisions.next().expect("ran out of decisions"))
+    }
+
+    fn no_follow_up(_: &str, _: String) -> Result<String> {
+        unreachable!()
+    }
+
+    const OLD: &str = "a\nb\nc\nd\n";
+    const NEW: &str = "a\nB\nc\nD\n// sass\n";
+
+    #[test]
+    fn segments_group_adjacent_changes() {
+        assert_eq!(
+            segments(OLD, NEW),
+            vec![
+                Segment::Same("a\n".into()),
+                Segment::Changed {
+                    old: "b\n".into(),
+                    new: "B\n".into()
+                },
+                Segment::Same("c\n".into()),
+                Segment::Changed {
+                    old: "d\n".into(),
+                    new: "D\n// sass\n".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn accept_all_is_the_output() {
+        let decide = scripted(vec![Decision::Accept, Decision::Accept]);
+        assert_eq!(review(OLD, NEW, decide, no_follow_up).unwrap(), NEW);
+    }
+
+    #[test]
+    fn reject_keeps_the_original_hunk() {
+        let decide = scripted(vec![Decision::Accept, Decision::Reject]);
+        let got = review(OLD, NEW, decide, no_follow_up).unwrap();
+        assert_eq!(got, "a\nB\nc\nd\n");
+    }
+
+    #[test]
+    fn follow_up_replaces_the_hunk_then_asks_again() {
+        let decide = scripted(vec![
+            Decision::Accept,
+            Decision::FollowUp("drop the sass".into()),
+            Decision::Accept,
+        ]);
+        let follow_up = |hunk: &str, transform: String| {
+            assert_eq!(hunk, "D\n// sass\n");
+            assert_eq!(transform, "drop the sass");
+            Ok("D\n".to_string())
+        };
+        let got = review(OLD, NEW, decide, follow_up).unwrap();
+        assert_eq!(got, "a\nB\nc\nD\n");
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use xdg::BaseDirectories;
+
+use crate::agent::ToolResult;
+use crate::config_files::Provider;
+
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    pub id: u64,
+    pub provider: Provider,
+    pub model: String,
+    pub original: String,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    pub pending: Vec<ToolResult>,
+    pub transcript: Value,
+    /// Set when `text` was changed after the model's last turn, e.g. during review.
+    #[serde(default)]
+    pub edited: bool,
+}
+
+// How many sessions to keep on disk; saving drops the oldest beyond this.
+const KEEP: usize = 100;
+
+pub fn new_id() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn dir() -> Result<PathBuf> {
+    Ok(BaseDirectories::with_prefix("refac")?.create_data_directory("sessions")?)
+}
+
+impl Session {
+    pub fn save(&self) -> Result<()> {
+        self.save_in(&dir()?)
+    }
+
+    pub fn load(id: Option<u64>) -> Result<Session> {
+        Session::load_in(&dir()?, id)
+    }
+
+    /// The message that continues this session with `transform`. The model's transcript
+    /// ends with its own last edits, so when the text was changed since, it is shown again.
+    pub fn follow_up(&self, transform: &str) -> String {
+        match self.edited {
+            true => format!(
+                "The text was changed after your last turn and now reads:\n\n{}\n\n{transform}",
+                self.text
+            ),
+            false => transform.to_string(),
+        }
+    }
+
+    fn save_in(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(format!("{}.json", self.id));
+        fs::write(&path, serde_json::to_string(self)?)
+            .with_context(|| format!("saving session to {}", path.display()))?;
+        prune(dir, KEEP)
+    }
+
+    fn load_in(dir: &Path, id: Option<u64>) -> Result<Session> {
+        let id = match id {
+            Some(id) => id,
+            None => latest(dir)?.ok_or_else(|| {
+                anyhow::anyhow!("no session to continue; run `refac tor` or `refac file` first")
+            })?,
+        };
+        let path = dir.join(format!("{id}.json"));
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("no session {id} at {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("session {id} did not parse"))
+    }
+}
+
+fn ids(dir: &Path) -> Result<Vec<u64>> {
+    let mut ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "json") {
+            ids.extend(
+                path.file_stem()
+                    .and_then(|s| s.to_str()?.parse::<u64>().ok()),
+            );
+        }
+    }
+    Ok(ids)
+}
+
+fn latest(dir: &Path) -> Result<Option<u64>> {
+    Ok(ids(dir)?.into_iter().max())
+}
+
+fn prune(dir: &Path, keep: usize) -> Result<()> {
+    let mut ids = ids(dir)?;
+    ids.sort_unstable();
+    for id in &ids[..ids.len().saturating_sub(keep)] {
+        let path = dir.join(format!("{id}.json"));
+        fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(id: u64, text: &str) -> Session {
+        Session {
+            id,
+            provider: Provider::Anthropic,
+            model: "m".into(),
+            original: "a".into(),
+            text: text.into(),
+            path: None,
+            pending: vec![ToolResult {
+                id: "tu_1".into(),
+                result: Ok("finished".into()),
+            }],
+            transcript: serde_json::json!([{ "role": "user" }]),
+            edited: false,
+        }
+    }
+
+    #[test]
+    fn loads_the_latest_session_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        session(9, "old").save_in(dir.path()).unwrap();
+        session(10, "new").save_in(dir.path()).unwrap();
+        let got = Session::load_in(dir.path(), None).unwrap();
+        assert_eq!(got.id, 10);
+        assert_eq!(got.text, "new");
+        assert_eq!(got.pending, session(10, "new").pending);
+        assert_eq!(Session::load_in(dir.path(), Some(9)).unwrap().text, "old");
+    }
+
+    #[test]
+    fn no_sessions_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Session::load_in(dir.path(), None).is_err());
+        assert!(Session::load_in(dir.path(), Some(1)).is_err());
+    }
+
+    #[test]
+    fn only_the_newest_sessions_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        for id in 1..=5 {
+            session(id, "t").save_in(dir.path()).unwrap();
+        }
+        prune(dir.path(), 2).unwrap();
+        let mut left = ids(dir.path()).unwrap();
+        left.sort_unstable();
+        assert_eq!(left, vec![4, 5]);
+    }
+
+    #[test]
+    fn edited_text_is_shown_in_the_follow_up() {
+        let mut s = session(1, "reviewed");
+        assert_eq!(s.follow_up("next"), "next");
+        s.edited = true;
+        let got = s.follow_up("next");
+        assert!(got.contains("reviewed") && got.ends_with("next"), "{got}");
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+use crate::agent::Usage;
+use crate::config_files::Price;
+use crate::history::{self, LogEntry};
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum GroupBy {
+    Day,
+    Month,
+    Provider,
+    Model,
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct Totals {
+    pub runs: usize,
+    pub usage: Usage,
+    pub cost: f64,
+    pub unpriced: usize,
+}
+
+pub fn cost(price: &Price, usage: &Usage) -> f64 {
+    let per_token = |per_million: f64| per_million / 1_000_000.0;
+    usage.input_tokens as f64 * per_token(price.input)
+        + usage.output_tokens as f64 * per_token(price.output)
+        + usage.cache_read_tokens as f64 * per_token(price.cache_read.unwrap_or(price.input))
+        + usage.cache_write_tokens as f64 * per_token(price.cache_write.unwrap_or(price.input))
+}
+
+fn key(entry: &LogEntry, by: GroupBy) -> String {
+    let when = history::when(entry);
+    match by {
+        GroupBy::Day => when.chars().take(10).collect(),
+        GroupBy::Month => when.chars().take(7).collect(),
+        GroupBy::Provider => format!("{:?}", entry.provider).to_lowercase(),
+        GroupBy::Model => entry.model.clone(),
+    }
+}
+
+pub fn group<'a>(
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+    by: GroupBy,
+) -> BTreeMap<String, Totals> {
+    let mut groups: BTreeMap<String, Totals> = BTreeMap::new();
+    for entry in entries {
+        let totals = groups.entry(key(entry, by)).or_default();
+        totals.runs += 1;
+        if let Some(usage) = entry.usage {
+            totals.usage += usage;
+        }
+        match entry.cost {
+            Some(cost) => totals.cost += cost,
+            None => totals.unpriced += 1,
+        }
+    }
+    groups
+}
+
+pub fn render(groups: &BTreeMap<String, Totals>) -> String {
+    let mut out = format!(
+        "{:<24} {:>6} {:>12} {:>12} {:>12} {:>12} {:>10}\n",
+        "", "runs", "input", "output", "cache read", "cache write", "cost"
+    );
+    let mut total = Totals::default();
+    let mut row = |name: &str, t: &Totals| {
+        let unpriced = if t.unpriced > 0 {
+            format!("  ({} unpriced)", t.unpriced)
+        } else {
+            String::new()
+        };
+        out += &format!(
+            "{:<24} {:>6} {:>12} {:>12} {:>12} {:>12} {:>10}{unpriced}\n",
+            name,
+            t.runs,
+            t.usage.input_tokens,
+            t.usage.output_tokens,
+            t.usage.cache_read_tokens,
+            t.usage.cache_write_tokens,
+            format!("${:.2}", t.cost),
+        );
+    };
+    for (name, t) in groups {
+        row(name, t);
+        total.runs += t.runs;
+        total.usage += t.usage;
+        total.cost += t.cost;
+        total.unpriced += t.unpriced;
+    }
+    row("total", &total);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_files::Provider;
+
+    fn entry(timestamp: u64, provider: Provider, input: u64, cost: Option<f64>) -> LogEntry {
+        LogEntry {
+            timestamp: Some(timestamp),
+            session: None,
+            provider,
+            model: "m".into(),
+            selected: String::new(),
+            transform: String::new(),
+            output: String::new(),
+            usage: Some(Usage {
+                input_tokens: input,
+                ..Usage::default()
+            }),
+            cost,
+        }
+    }
+
+    #[test]
+    fn cost_bills_cache_tokens_at_the_input_price_by_default() {
+        let usage = Usage {
+            input_tokens: 1_000_000,
+            output_tokens: 100_000,
+            cache_read_tokens: 2_000_000,
+            cache_write_tokens: 1_000_000,
+        };
+        let price = Price {
+            input: 3.0,
+            output: 15.0,
+            cache_read: Some(0.3),
+            cache_write: None,
+        };
+        assert!((cost(&price, &usage) - (3.0 + 1.5 + 0.6 + 3.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn runs_are_grouped_and_totalled() {
+        let day = 86_400;
+        let entries = [
+            entry(0, Provider::Anthropic, 10, Some(0.5)),
+            entry(60, Provider::Openai, 5, None),
+            entry(day, Provider::Anthropic, 1, Some(0.25)),
+        ];
+        let by_day = group(&entries, GroupBy::Day);
+        assert_eq!(
+            by_day.keys().collect::<Vec<_>>(),
+            ["1970-01-01", "1970-01-02"]
+        );
+        let first = &by_day["1970-01-01"];
+        assert_eq!((first.runs, first.usage.input_tokens), (2, 15));
+        assert_eq!((first.cost, first.unpriced), (0.5, 1));
+
+        let by_provider = group(&entries, GroupBy::Provider);
+        assert_eq!(by_provider["anthropic"].cost, 0.75);
+        assert_eq!(by_provider["openai"].runs, 1);
+
+        let table = render(&by_provider);
+        let total = table.lines().last().unwrap();
+        assert!(total.starts_with("total"));
+        assert!(total.contains("$0.75"));
+        assert!(total.ends_with("(1 unpriced)"));
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use tree_sitter::{Language, Node, Parser};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Lang {
+    Rust,
+    Python,
+    Javascript,
+    Typescript,
+    Tsx,
+    Go,
+    C,
+    Json,
+}
+
+impl Lang {
+    pub fn from_path(path: &Path) -> Option<Lang> {
+        Some(match path.extension()?.to_str()? {
+            "rs" => Lang::Rust,
+            "py" | "pyi" => Lang::Python,
+            "js" | "mjs" | "cjs" | "jsx" => Lang::Javascript,
+            "ts" | "mts" | "cts" => Lang::Typescript,
+            "tsx" => Lang::Tsx,
+            "go" => Lang::Go,
+            "c" | "h" => Lang::C,
+            "json" => Lang::Json,
+            _ => return None,
+        })
+    }
+
+    fn language(self) -> Language {
+        match self {
+            Lang::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Lang::Python => tree_sitter_python::LANGUAGE.into(),
+            Lang::Javascript => tree_sitter_javascript::LANGUAGE.into(),
+            Lang::Typescript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Lang::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Lang::Go => tree_sitter_go::LANGUAGE.into(),
+            Lang::C => tree_sitter_c::LANGUAGE.into(),
+            Lang::Json => tree_sitter_json::LANGUAGE.into(),
+        }
+    }
+}
+
+const MAX_REPORTED: usize = 10;
+
+// Describes where `text` fails to parse, at most MAX_REPORTED places; empty when it parses.
+pub fn errors(lang: Lang, text: &str) -> Vec<String> {
+    let mut parser = Parser::new();
+    if parser.set_language(&lang.language()).is_err() {
+        return Vec::new();
+    }
+    let Some(tree) = parser.parse(text, None) else {
+        return Vec::new();
+    };
+    let mut out = Vec::new();
+    collect(tree.root_node(), text, &mut out);
+    out
+}
+
+fn collect(node: Node, text: &str, out: &mut Vec<String>) {
+    if out.len() >= MAX_REPORTED || !node.has_error() && !node.is_missing() {
+        return;
+    }
+    let at = node.start_position();
+    let (line, column) = (at.row + 1, at.column + 1);
+    if node.is_missing() {
+        out.push(format!(
+            "line {line}, column {column}: missing {}",
+            node.kind()
+        ));
+    } else if node.is_error() {
+        let snippet: String = text[node.byte_range()].chars().take(40).collect();
+        out.push(format!(
+            "line {line}, column {column}: unexpected {snippet:?}"
+        ));
+    } else {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            collect(child, text, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_comes_from_the_extension() {
+        assert_eq!(Lang::from_path(Path::new("src/main.rs")), Some(Lang::Rust));
+        assert_eq!(Lang::from_path(Path::new("a/b.tsx")), Some(Lang::Tsx));
+        assert_eq!(Lang::from_path(Path::new("README.md")), None);
+        assert_eq!(Lang::from_path(Path::new("Makefile")), None);
+    }
+
+    #[test]
+    fn reports_where_parsing_fails() {
+        assert!(errors(Lang::Rust, "fn main() { let x = 1; }\n").is_empty());
+        let got = errors(Lang::Rust, "fn main() {\n    let x = ;\n}\n");
+        assert!(!got.is_empty());
+        assert!(got[0].starts_with("line 2"), "{got:?}");
+        let got = errors(Lang::Python, "def f(:\n    pass\n");
+        assert!(!got.is_empty());
+        assert!(errors(Lang::Json, "{\"a\": [1, 2]}").is_empty());
+    }
+}